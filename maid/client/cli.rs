@@ -1,27 +1,28 @@
 pub(crate) mod dispatch;
 pub(crate) mod script;
 pub(crate) mod tasks;
+pub(crate) mod watch;
 
 use crate::{parse, server, task};
 
 use maid::{
+    graph::Graph,
     helpers,
     log::prelude::*,
     models::{
-        client::{CacheConfig, Dependency, Task},
-        shared::{Cache, Project},
+        client::{CacheKey, Dependency, Outcome, Task},
+        shared::{Cache, Maidfile, Project},
     },
+    table, Error, Result,
 };
 
-use fs_extra::dir::get_size;
-use global_placeholders::global;
 use human_bytes::human_bytes;
-use std::{env, fs, path::Path, time::Instant};
+use std::{env, path::Path, time::Instant};
+use toml::Value;
 
 use macros_rs::{
-    exp::ternary,
+    exp::{then, ternary},
     fmt::{fmtstr, string},
-    fs::{file_exists, folder_exists},
 };
 
 pub(crate) fn get_version(short: bool) -> String {
@@ -32,9 +33,9 @@ pub(crate) fn get_version(short: bool) -> String {
     }
 }
 
-pub(crate) fn info(path: &String) {
-    let values = parse::merge(path).project;
-    let project_root = parse::file::find_maidfile_root(path);
+pub(crate) fn info(path: &String) -> Result<()> {
+    let values = parse::merge(path)?.project;
+    let project_root = parse::file::find_maidfile_root(path)?;
 
     let project_name = match values.to_owned() {
         Some(project) => project.name,
@@ -53,16 +54,20 @@ pub(crate) fn info(path: &String) {
 
     match project_version {
         Some(version) => println!(
-            "{}\n{}",
-            format!("{}: {}", "Version".white(), version.bright_yellow()),
-            format!("{}: {}", "Directory".white(), project_root.to_string_lossy().bright_yellow())
+            "{}: {}\n{}: {}",
+            "Version".white(),
+            version.bright_yellow(),
+            "Directory".white(),
+            project_root.to_string_lossy().bright_yellow()
         ),
-        None => println!("{}", format!("{}: {}", "Directory".white(), project_root.to_string_lossy().bright_yellow())),
+        None => println!("{}: {}", "Directory".white(), project_root.to_string_lossy().bright_yellow()),
     };
+
+    Ok(())
 }
 
-pub(crate) fn env(path: &String) {
-    let values = parse::merge(path);
+pub(crate) fn env(path: &String) -> Result<()> {
+    let values = parse::merge(path)?;
 
     let project_name = match values.project {
         Some(project) => project.name,
@@ -78,157 +83,196 @@ pub(crate) fn env(path: &String) {
         for (key, value) in env {
             println!("{}{}{value}", key.bright_cyan(), "=".white())
         }
+
+        Ok(())
     } else {
-        error!("No ENV values defined for this project")
+        Err(Error::NotFound(string!("No ENV values defined for this project")))
+    }
+}
+
+// the task's path is relative to the project, unless it asks for the directory maid was started in
+fn working_path(values: &Maidfile<Value>, task: &str, project_root: &Path, cwd: &str) -> String {
+    match &values.tasks[task].path {
+        Some(path) => ternary!(path.is_empty(), helpers::string::path_to_str(project_root), ternary!(path == "%{dir.current}", cwd, path)),
+        None => helpers::string::path_to_str(project_root),
+    }
+    .to_string()
+}
+
+fn cache_key(values: &Maidfile<Value>, task: &str, cache: &Cache, args: &[String], project: &Path, dir: &Path) -> Result<CacheKey> {
+    let table = table::create(values.clone(), args, project.to_path_buf())?;
+    let script = script::lines(&values.tasks[task].script)?;
+    let shell = crate::shell::interpreter(values.tasks[task].shell.as_ref(), values.project.as_ref().and_then(|project| project.shell.as_ref()));
+
+    Ok(task::cache::create_key(dir, cache, shell.as_deref(), &script, &table))
+}
+
+pub(crate) fn explain_cache(path: &String, task: &str, args: &[String]) -> Result<()> {
+    let values = parse::merge(path)?;
+    let project_root = parse::file::find_maidfile_root(path)?;
+
+    let cache = match values.tasks.get(task) {
+        Some(item) => match &item.cache {
+            Some(cache) => cache.clone(),
+            None => return Err(Error::NotFound(format!("Task '{task}' does not define a cache."))),
+        },
+        None => return Err(Error::MissingTask(string!(task))),
+    };
+
+    let args = task::args::resolve(task, &values.tasks[task], args, true)?;
+    let dir = project_root.join(working_path(&values, task, &project_root, &helpers::file::get_current_working_dir()?));
+    let current = cache_key(&values, task, &cache, &args, &project_root, &dir)?;
+    let config = match task::store::read(&dir, task) {
+        Some(config) => config,
+        None => {
+            warn!("No build cache recorded for '{task}'");
+            return Ok(());
+        }
+    };
+
+    let changes = task::cache::changes(&config.key, &current);
+    info!("Cache for {task}\n");
+
+    for component in ["version", "inputs", "script", "table"] {
+        match changes.iter().find(|(name, _)| *name == component) {
+            Some((_, details)) if !details.is_empty() => println!("{}{} {} {}", component.white(), maid::colors::SEP, "changed".bright_red(), format!("[{}]", details.join(", ")).white()),
+            Some(_) => println!("{}{} {}", component.white(), maid::colors::SEP, "changed".bright_red()),
+            None => println!("{}{} {}", component.white(), maid::colors::SEP, "unchanged".bright_green()),
+        }
     }
+
+    match config.hash == task::cache::digest(&current) {
+        true => println!("\n{} {}", maid::colors::OK, "next run will restore from cache".bright_green()),
+        false => println!("\n{} {}", maid::colors::FAIL, "next run will rebuild".bright_red()),
+    }
+
+    Ok(())
 }
 
-pub(crate) fn exec(task: &str, args: &Vec<String>, path: &String, silent: bool, is_dep: bool, is_remote: bool, log_level: Option<tracing::Level>, force: bool, log_deps: bool) {
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn exec(task: &str, args: &[String], path: &String, silent: bool, is_dep: bool, is_remote: bool, detach: bool, log_level: Option<tracing::Level>, force: bool, log_deps: bool, jobs: usize) -> Result<Outcome> {
     debug!("Starting maid {}", env!("CARGO_PKG_VERSION"));
 
     if task.is_empty() {
         if is_remote {
-            tasks::list_remote(path, silent, log_level);
+            tasks::list_remote(path, silent, log_level, jobs)?;
         } else {
-            tasks::list_all(path, silent, log_level, force);
+            tasks::list_all(path, silent, log_level, force, jobs)?;
         }
+
+        Ok(Outcome::Ran)
     } else {
-        let values = parse::merge(path);
-        let project_root = parse::file::find_maidfile_root(path);
-        let cwd = &helpers::file::get_current_working_dir();
+        let values = parse::merge(path)?;
+        let project_root = parse::file::find_maidfile_root(path)?;
+        let cwd = &helpers::file::get_current_working_dir()?;
 
-        if values.tasks.get(task).is_none() {
-            error!("Could not find the task '{task}'. Does it exist?");
+        if !values.tasks.contains_key(task) {
+            return Err(Error::MissingTask(string!(task)));
         }
 
         if is_remote && values.tasks.get(task).unwrap().remote.is_none() {
-            error!("Could not find the remote task '{task}'. Does it exist?");
+            return Err(Error::NotFound(format!("Could not find the remote task '{task}'. Does it exist?")));
         }
 
-        match values.tasks.get(task).unwrap().remote.as_ref() {
-            Some(val) => {
-                if val.exclusive && !is_remote {
-                    error!("Task '{task}' is remote only.");
-                }
+        if let Some(val) = values.tasks.get(task).unwrap().remote.as_ref() {
+            if val.exclusive && !is_remote {
+                return Err(Error::RemoteOnly(string!(task)));
             }
-            None => {}
         }
 
-        if !is_remote {
-            match &values.tasks[task].depends {
-                Some(deps) => {
-                    let start = Instant::now();
-                    let ticks = vec!["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
-                    let template = fmtstr!("{{prefix:.white}} {{spinner:.yellow}}{{msg}} {}", "({elapsed})".bright_cyan());
-                    let pb = task::progress::init(ticks, template, 80);
-
-                    for (index, item) in deps.iter().enumerate() {
-                        let is_verbose_dep = item.starts_with("log:");
-                        let name = item.strip_prefix("log:").unwrap_or(item.as_str());
-
-                        pb.set_prefix(format!("[{}/{}]", index + 1, deps.len()));
-                        pb.set_message(fmtstr!("{} {name}", "running dependency".bright_yellow()));
-
-                        exec(&name, args, path, true, true, is_remote, log_level, force, is_verbose_dep);
-                    }
-
-                    if !is_dep {
-                        task::progress::finish();
-                        println!(
-                            "{} {} in {} {}\n",
-                            maid::colors::OK,
-                            format!("finished {} {}", deps.len(), ternary!(deps.len() > 1, "dependencies", "dependency")).bright_green(),
-                            format!("{:.2?}", start.elapsed()).yellow(),
-                            format!("[{}]", deps.join(", ")).white()
-                        )
-                    }
-                }
-                None => {}
-            };
+        let args = &task::args::resolve(task, &values.tasks[task], args, !is_dep)?;
+
+        if !is_remote && !is_dep {
+            let graph = Graph::new(&values, task)?;
+
+            let deps = graph.dependencies();
+
+            if !deps.is_empty() {
+                let start = Instant::now();
+                let ticks = vec!["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+                let template = fmtstr!("{{prefix:.white}} {{spinner:.yellow}}{{msg}} {}", "({elapsed})".bright_cyan());
+                let pb = task::progress::init(ticks, template, 80);
+
+                let outcomes = task::scheduler::run(
+                    &graph,
+                    jobs,
+                    |node| exec(&node.name, args, path, true, true, is_remote, false, log_level, force, node.verbose, jobs),
+                    |progress| {
+                        pb.set_prefix(format!("[{}/{}]", progress.started, progress.total));
+                        pb.set_message(fmtstr!("{} {}", "running dependency".bright_yellow(), progress.running.join(", ")));
+                    },
+                );
+
+                task::progress::finish();
+                let outcomes = outcomes?;
+                let names: Vec<&str> = deps.iter().map(|node| node.name.as_str()).collect();
+                let cached = outcomes.iter().filter(|outcome| **outcome == Outcome::Cached).count();
+
+                println!(
+                    "{} {}{} in {} {}\n",
+                    maid::colors::OK,
+                    format!("finished {} {}", deps.len(), ternary!(deps.len() > 1, "dependencies", "dependency")).bright_green(),
+                    ternary!(cached > 0, format!(" ({cached} cached)").bright_magenta(), "".normal()),
+                    format!("{:.2?}", start.elapsed()).yellow(),
+                    format!("[{}]", names.join(", ")).white()
+                )
+            }
         }
 
         let cache = match &values.tasks[task].cache {
             Some(cache) => cache.clone(),
-            None => Cache { path: string!(""), target: vec![] },
+            None => Cache::default(),
         };
 
-        let task_path = match &values.tasks[task].path {
-            Some(path) => ternary!(path == "", helpers::string::path_to_str(project_root.as_path()), ternary!(path == "%{dir.current}", cwd, path)),
-            None => helpers::string::path_to_str(project_root.as_path()),
-        }
-        .to_string();
+        let task_path = working_path(&values, task, &project_root, cwd);
+        let working_dir = project_root.join(&task_path);
 
-        if !cache.path.trim().is_empty() && !cache.target.is_empty() && !is_remote {
-            if !folder_exists!(&global!("maid.cache_dir", task)) {
-                fs::create_dir_all(global!("maid.cache_dir", task)).unwrap();
-                debug!("created maid cache dir");
-            }
+        let mut key: Option<CacheKey> = None;
 
-            let hash = task::cache::create_hash(&cache.path);
-            let config_path = format!(".maid/cache/{task}/{}.toml", task);
-
-            if !file_exists!(&config_path) {
-                match fs::write(
-                    config_path.clone(),
-                    toml::to_string(&CacheConfig {
-                        target: cache.target.clone(),
-                        hash: string!(""),
-                    })
-                    .unwrap(),
-                ) {
-                    Ok(_) => debug!("Created {task} cache config"),
-                    Err(err) => error!(%err, "Cannot create cache config"),
-                };
-            }
+        if !cache.path.is_empty() && !cache.target.is_empty() && !is_remote {
+            let current = cache_key(&values, task, &cache, args, &project_root, &working_dir)?;
+            let hash = task::cache::digest(&current);
 
-            let contents = match fs::read_to_string(config_path.clone()) {
-                Ok(content) => content,
-                Err(err) => error!(%err, "Cannot read cache config"),
+            let stored = match task::store::read(&working_dir, task) {
+                Some(config) if config.hash == hash && config.target == cache.target => Some(config),
+                _ if !force => server::cache::fetch(&values, task, &hash, &working_dir),
+                _ => None,
             };
 
-            let json = match toml::from_str::<CacheConfig>(&contents) {
-                Ok(contents) => contents,
-                Err(err) => error!(%err, "Cannot read cache config"),
-            };
+            match stored {
+                Some(config) if !force => {
+                    then!(!is_dep, println!("{}", "skipping task due to cached files".bright_magenta()));
+
+                    match task::store::restore(&working_dir, &config) {
+                        Ok(_) => {
+                            match task::progress::get().filter(|_| is_dep) {
+                                Some(pb) => pb.println(format!("{} {}", format!("[{task}]").white(), "cached".bright_magenta())),
+                                None => {
+                                    for target in &config.target {
+                                        println!(
+                                            "{} ({})",
+                                            format!("copied target '{}' from cache", target).magenta(),
+                                            human_bytes(task::store::size(&config, target) as f64).white()
+                                        );
+                                    }
+                                }
+                            }
 
-            if json.hash == hash && !is_dep && !force {
-                println!("{}", "skipping task due to cached files".bright_magenta());
-
-                for target in cache.target.clone() {
-                    let cache_file = format!(".maid/cache/{task}/target/{}", Path::new(&target.clone()).file_name().unwrap().to_str().unwrap());
-
-                    println!(
-                        "{} ({})",
-                        format!("copied target '{}' from cache", target.clone()).magenta(),
-                        format!("{}", human_bytes(get_size(cache_file.clone()).unwrap_or_default() as f64).white())
-                    );
-
-                    match fs::copy(Path::new(&cache_file), target.to_owned()) {
-                        Ok(_) => debug!("copied target file {}", target),
-                        Err(_) => {
-                            println!("");
-                            match fs::remove_dir_all(format!(".maid/cache/{task}")) {
-                                Ok(_) => warn!("Cannot copy target file, rebuilt build cache"),
-                                Err(_) => error!("Build cache does not exist, cannot remove"),
+                            return Ok(Outcome::Cached);
+                        }
+                        Err(err) => {
+                            println!();
+                            match task::store::remove(&working_dir, task) {
+                                Ok(_) => warn!(%err, "Cannot restore target files, rebuilt build cache"),
+                                Err(err) => return Err(Error::Cache(err)),
                             }
                         }
-                    };
+                    }
                 }
+                _ => {}
+            };
 
-                std::process::exit(0);
-            } else {
-                match fs::write(
-                    config_path.clone(),
-                    toml::to_string(&CacheConfig {
-                        target: cache.target,
-                        hash: hash.to_owned(),
-                    })
-                    .unwrap(),
-                ) {
-                    Ok(_) => debug!("added hash for {task} -> {hash}"),
-                    Err(err) => error!(%err, "error creating cache config"),
-                };
-            }
+            key = Some(current);
         };
 
         debug!("Is remote?: {is_remote}");
@@ -246,17 +290,21 @@ pub(crate) fn exec(task: &str, args: &Vec<String>, path: &String, silent: bool,
         }
 
         if is_remote {
-            server::cli::remote(Task {
-                silent,
-                maidfile: values.clone(),
-                name: string!(task),
-                project: project_root,
-                remote: values.tasks[task].remote.clone(),
-                script: values.tasks[task].script.clone(),
-                path: task_path.clone(),
-                args: args.clone(),
-                dep: Dependency { active: is_dep, verbose: log_deps },
-            });
+            server::cli::remote(
+                Task {
+                    silent,
+                    maidfile: values.clone(),
+                    name: string!(task),
+                    project: project_root,
+                    remote: values.tasks[task].remote.clone(),
+                    script: values.tasks[task].script.clone(),
+                    path: task_path.clone(),
+                    args: args.clone(),
+                    dep: Dependency { active: is_dep, verbose: log_deps },
+                    key: None,
+                },
+                detach,
+            )?;
         } else {
             dispatch::task(Task {
                 silent,
@@ -268,7 +316,10 @@ pub(crate) fn exec(task: &str, args: &Vec<String>, path: &String, silent: bool,
                 path: task_path.clone(),
                 args: args.clone(),
                 dep: Dependency { active: is_dep, verbose: log_deps },
-            });
+                key,
+            })?;
         }
+
+        Ok(Outcome::Ran)
     }
 }
@@ -1,63 +1,174 @@
 use maid::{
-    helpers,
     log::prelude::*,
     models::client::{Runner, Task, UpdateData},
+    Error, Result,
 };
 
+use global_placeholders::global;
 use inquire::Text;
 use macros_rs::fs::file_exists;
-use notify::RecursiveMode;
-use notify_debouncer_mini::new_debouncer;
 use reqwest::blocking;
-use std::{fs::File, io::Write, path::Path, time::Duration};
+use url::Url;
+use uuid::Uuid;
+
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
 
-// rewrite
-pub(crate) fn watch(path: &Path) {
-    let (tx, rx) = std::sync::mpsc::channel();
-    let mut debouncer = new_debouncer(Duration::from_secs(1), tx).unwrap();
+// MAID_UPDATE_URL points the updater at a mirror instead of the release api
+fn update_url() -> String { std::env::var("MAID_UPDATE_URL").unwrap_or_else(|_| global!("maid.update_url")).trim_end_matches('/').to_string() }
 
-    debouncer.watcher().watch(path, RecursiveMode::Recursive).unwrap();
-    for events in rx {
-        if let Ok(event) = events {
-            println!("{:?}", event);
-        }
-    }
-}
+fn client() -> blocking::Client { blocking::Client::builder().timeout(Duration::from_secs(300)).connect_timeout(Duration::from_secs(5)).build().unwrap_or_default() }
 
-pub(crate) fn check_update() {
-    let checker = match blocking::get("https://api.maid.ci/versions/latest") {
-        Ok(res) => res.json::<UpdateData>(),
-        Err(err) => error!(%err, "Unable to check for updates"),
+fn latest() -> Result<UpdateData> {
+    let response = match client().get(format!("{}/versions/latest", update_url())).send().and_then(|res| res.error_for_status()) {
+        Ok(res) => res,
+        Err(err) => return Err(Error::Remote(format!("Unable to check for updates: {err}"))),
     };
 
-    let version = match checker {
-        Ok(body) => body.version,
-        Err(err) => error!(%err, "Unable to check for updates"),
-    };
+    response.json::<UpdateData>().map_err(|err| Error::Remote(format!("Unable to check for updates: {err}")))
+}
+
+pub(crate) fn check_update() -> Result<()> {
+    let version = latest()?.version;
 
     if version == env!("CARGO_PKG_VERSION") {
         info!("Maid is currently on the latest version")
     } else {
-        warn!("Your install is currently out of date.\n\nThe current version is {version}\nPlease update using `maid upgrade`")
+        warn!("Your install is currently out of date.\n\nThe current version is {version}\nPlease update using `maid --system upgrade`")
+    }
+
+    Ok(())
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    match client().get(url).send().and_then(|res| res.error_for_status()).and_then(|res| res.bytes()) {
+        Ok(bytes) => Ok(bytes.to_vec()),
+        Err(err) => Err(Error::Remote(format!("Unable to download {url}: {err}"))),
     }
 }
 
-pub(crate) fn clean() {
-    if let Ok(_) = std::fs::remove_dir_all(".maid/temp") {
+// runs the binary to make sure it actually works on this machine
+fn verify(binary: &Path, version: &str) -> Result<()> {
+    let output = Command::new(binary).arg("--version").output().map_err(|err| Error::Command { name: binary.display().to_string(), source: err })?;
+    let reported = String::from_utf8_lossy(&output.stdout);
+
+    match output.status.success() && reported.contains(version) {
+        true => Ok(()),
+        false => Err(Error::Status(format!("Downloaded binary did not report version {version}"))),
+    }
+}
+
+pub(crate) fn upgrade(force: bool) -> Result<()> {
+    let data = latest()?;
+
+    if data.version == env!("CARGO_PKG_VERSION") && !force {
+        info!("Maid is currently on the latest version");
+        return Ok(());
+    }
+
+    let base = Url::parse(&format!("{}/", update_url())).map_err(|err| Error::Remote(format!("Invalid update url: {err}")))?;
+    let artifact = match base.join(&format!("{}/{}", data.download.trim_end_matches('/'), env!("TARGET"))) {
+        Ok(url) => url.to_string(),
+        Err(err) => return Err(Error::Remote(format!("Invalid download url '{}': {err}", data.download))),
+    };
+
+    info!("Downloading maid {} for {}", data.version, env!("TARGET"));
+
+    // an integrity check only, the checksum is served next to the binary so whoever controls the
+    // update server controls both. nothing here proves the release came from the maid authors
+    let binary = download(&artifact)?;
+    let expected = String::from_utf8_lossy(&download(&format!("{artifact}.blake3"))?).split_whitespace().next().unwrap_or_default().to_lowercase();
+    let actual = blake3::hash(&binary).to_hex().to_string();
+
+    if expected != actual {
+        return Err(Error::Status(format!("Checksum mismatch for {artifact}, expected {expected} but got {actual}")));
+    }
+
+    let current = std::env::current_exe().and_then(|path| path.canonicalize()).map_err(|err| Error::io("Unable to locate maid executable", err))?;
+    let staged = current.with_file_name(format!(".maid-upgrade-{}", Uuid::new_v4()));
+    let backup = current.with_file_name(format!(".maid-backup-{}", Uuid::new_v4()));
+
+    std::fs::write(&staged, &binary).map_err(|err| Error::io("Unable to write new binary", err))?;
+
+    #[cfg(unix)]
+    if let Err(err) = std::fs::metadata(&current).and_then(|meta| std::fs::set_permissions(&staged, meta.permissions())) {
+        let _ = std::fs::remove_file(&staged);
+        return Err(Error::io("Unable to set permissions on new binary", err));
+    }
+
+    if let Err(err) = verify(&staged, &data.version) {
+        let _ = std::fs::remove_file(&staged);
+        return Err(err);
+    }
+
+    if let Err(err) = std::fs::rename(&current, &backup) {
+        let _ = std::fs::remove_file(&staged);
+        return Err(Error::io("Unable to move current binary aside", err));
+    }
+
+    let installed = std::fs::rename(&staged, &current).map_err(|err| Error::io("Unable to install new binary", err)).and_then(|_| verify(&current, &data.version));
+
+    if let Err(err) = installed {
+        let _ = std::fs::remove_file(&staged);
+        return match std::fs::rename(&backup, &current) {
+            Ok(_) => Err(err),
+            Err(restore) => Err(Error::io(format!("{err}, rollback failed, previous binary is at {}", backup.display()), restore)),
+        };
+    }
+
+    if std::fs::remove_file(&backup).is_err() {
+        debug!("previous binary left at {}", backup.display());
+    }
+
+    println!("{} {}", maid::colors::OK, format!("upgraded maid {} -> {}", env!("CARGO_PKG_VERSION"), data.version).bright_green());
+
+    Ok(())
+}
+
+// every task keeps its cache in its own working dir, so each of them is emptied along with the current one
+pub(crate) fn clean(path: &String) -> Result<()> {
+    let cwd = maid::helpers::file::get_current_working_dir()?;
+    let mut dirs = BTreeSet::from([PathBuf::from(&cwd)]);
+
+    if let (Ok(values), Ok(project_root)) = (crate::parse::merge(path), crate::parse::file::find_maidfile_root(path)) {
+        dirs.insert(project_root.clone());
+        dirs.extend(values.tasks.keys().map(|task| project_root.join(super::working_path(&values, task, &project_root, &cwd))));
+    }
+
+    let (mut temp, mut cache) = (false, false);
+
+    for dir in &dirs {
+        temp |= std::fs::remove_dir_all(dir.join(".maid/temp")).is_ok();
+
+        if std::fs::remove_dir_all(dir.join(".maid/cache")).is_ok() {
+            debug!("emptied build cache in {}", dir.display());
+            cache = true;
+        }
+    }
+
+    if temp {
         info!("Purged temp archives")
     }
 
-    match std::fs::remove_dir_all(".maid/cache") {
-        Ok(_) => info!("Emptied build cache"),
-        Err(_) => warn!("Build cache does not exist, cannot remove"),
+    match cache {
+        true => info!("Emptied build cache"),
+        false => warn!("Build cache does not exist, cannot remove"),
     };
+
+    Ok(())
 }
 
 // improve
-pub(crate) fn init() {
-    fn create_error(name: &str, path: &str) {
-        std::fs::remove_file(path).unwrap();
-        error!("An error happened when asking for {}, try again later.", name);
+pub(crate) fn init() -> Result<()> {
+    fn create_error(name: &str, path: &str) -> Error {
+        let _ = std::fs::remove_file(path);
+        Error::Status(format!("An error happened when asking for {name}, try again later."))
     }
 
     let path = "maidfile";
@@ -66,20 +177,20 @@ pub(crate) fn init() {
     if !file_exists!(path) {
         println!("This utility will walk you through creating a maidfile.\n");
 
-        let mut file = File::create(&path).unwrap();
-        let current_dir = std::env::current_dir().unwrap();
+        let mut file = File::create(path).map_err(|err| Error::io("Unable to create maidfile", err))?;
+        let current_dir = std::env::current_dir().map_err(|err| Error::io("Unable to read current directory", err))?;
         writeln!(&mut file, "[project]").unwrap();
 
-        let name = Text::new("project name:").with_default(&current_dir.file_name().unwrap().to_str().unwrap().to_string()).prompt();
+        let name = Text::new("project name:").with_default(current_dir.file_name().unwrap().to_str().unwrap()).prompt();
         let version = Text::new("version:").with_default("1.0.0").prompt();
 
         match name {
             Ok(name) => writeln!(&mut file, "name = \"{name}\"").unwrap(),
-            Err(_) => create_error("project name", &path),
+            Err(_) => return Err(create_error("project name", path)),
         }
         match version {
             Ok(version) => writeln!(&mut file, "version = \"{version}\"").unwrap(),
-            Err(_) => create_error("version", &path),
+            Err(_) => return Err(create_error("version", path)),
         }
 
         writeln!(&mut file, "\n{example_maidfile}").unwrap();
@@ -90,32 +201,22 @@ pub(crate) fn init() {
     } else {
         println!("{}", "maidfile already exists, aborting".yellow())
     }
+
+    Ok(())
 }
 
-pub(crate) fn task(task: Task<toml::Value>) {
-    let mut script: Vec<String> = Vec::new();
-
-    if let Some(cmd) = task.script.as_str() {
-        script.push(cmd.to_string());
-    } else if let Some(array) = task.script.as_array() {
-        for value in array {
-            match value.as_str() {
-                Some(cmd) => script.push(cmd.to_string()),
-                None => error!("Unable to parse Maidfile. Missing string value."),
-            }
-        }
-    } else {
-        helpers::status::error(task.script.type_str());
-    }
+pub(crate) fn task(task: Task<toml::Value>) -> Result<()> {
+    let script = super::script::lines(&task.script)?;
 
     super::script::run_wrapped(Runner {
         script,
         dep: task.dep,
+        key: task.key,
         name: task.name,
         path: task.path,
         args: task.args,
         silent: task.silent,
         project: task.project,
         maidfile: task.maidfile,
-    });
+    })
 }
@@ -2,54 +2,76 @@ use maid::{
     helpers,
     log::prelude::*,
     models::{client::Runner, shared::Cache},
-    table,
+    table, Error, Result,
 };
 
 use std::{
-    env,
-    io::{BufRead, BufReader, Error},
+    io::{BufRead, BufReader},
     path::Path,
-    process::{Child, Command, ExitStatus, Stdio},
+    process::{Child, Command, Stdio},
     time::Instant,
 };
 
-use crate::{shell::IntoArgs, task};
-use fs_extra::dir::get_size;
+use crate::{server, shell::IntoArgs, task};
 use human_bytes::human_bytes;
 use text_placeholder::Template;
 
-pub(crate) fn run_wrapped(runner: Runner<toml::Value>) {
+pub(crate) fn lines(script: &toml::Value) -> Result<Vec<String>> {
+    let mut lines: Vec<String> = Vec::new();
+
+    if let Some(cmd) = script.as_str() {
+        lines.push(cmd.to_string());
+    } else if let Some(array) = script.as_array() {
+        for value in array {
+            match value.as_str() {
+                Some(cmd) => lines.push(cmd.to_string()),
+                None => return Err(Error::Parse("Unable to parse Maidfile. Missing string value.".to_string())),
+            }
+        }
+    } else {
+        return Err(helpers::status::error(script.type_str()));
+    }
+
+    Ok(lines)
+}
+
+pub(crate) fn run_wrapped(runner: Runner<toml::Value>) -> Result<()> {
     let start = Instant::now();
 
     let mut cmd: Child;
-    let mut status_array: Vec<Result<ExitStatus, Error>> = vec![];
+    let mut failed: Option<Error> = None;
+    let continue_on_error = runner.maidfile.tasks[&runner.name].continue_on_error.unwrap_or(false);
+    let shell = crate::shell::interpreter(runner.maidfile.tasks[&runner.name].shell.as_ref(), runner.maidfile.project.as_ref().and_then(|project| project.shell.as_ref()));
+
+    let working_dir = runner.project.join(Path::new(&runner.path));
 
-    for string in runner.script {
+    for (index, string) in runner.script.iter().enumerate() {
         let start = Instant::now();
 
-        let working_dir = runner.project.join(Path::new(&runner.path));
-        let table = table::create(runner.maidfile.to_owned(), &runner.args, runner.project.to_owned());
-        let script = Template::new_with_placeholder(&string, "%{", "}").fill_with_hashmap(&table);
+        let table = table::create(runner.maidfile.to_owned(), &runner.args, runner.project.to_owned())?;
+        let script = Template::new_with_placeholder(string, "%{", "}").fill_with_hashmap(&table);
 
-        let (name, args) = match script.try_into_args() {
-            Ok(mut args) => (args.remove(0), args),
-            Err(err) => error!(%err, "Script could not be parsed into args"),
+        let (name, args) = match &shell {
+            Some(shell) => (shell[0].to_owned(), shell[1..].iter().cloned().chain([script.to_owned()]).collect::<Vec<_>>()),
+            None => match script.try_into_args() {
+                Ok(mut args) if !args.is_empty() => (args.remove(0), args),
+                Ok(_) => return Err(Error::Parse(format!("Script line {} of {} is empty", index + 1, runner.name))),
+                Err(err) => return Err(Error::Parse(format!("Script could not be parsed into args: {err}"))),
+            },
         };
 
         debug!("Original Script: {string}");
         debug!("Parsed Script: {script}");
         debug!("Execute Command: '{name} {}'", args.join(" "));
 
-        match env::set_current_dir(&working_dir) {
-            Ok(_) => debug!("Working directory: {working_dir:?}"),
-            Err(err) => error!(%err, "Failed to set working directory {working_dir:?}"),
-        };
+        debug!("Working directory: {working_dir:?}");
 
         if runner.dep.active {
             let is_verbose = runner.dep.verbose;
             let pb = task::progress::get().unwrap();
 
             cmd = match Command::new(&name)
+                .current_dir(&working_dir)
                 .stdout(if is_verbose { Stdio::piped() } else { Stdio::null() })
                 .stderr(if is_verbose { Stdio::piped() } else { Stdio::null() })
                 .stdin(Stdio::null())
@@ -66,17 +88,18 @@ pub(crate) fn run_wrapped(runner: Runner<toml::Value>) {
                             stdout
                                 .lines()
                                 .chain(stderr.lines())
-                                .filter_map(Result::ok)
+                                .filter_map(std::result::Result::ok)
                                 .filter(|_| is_verbose)
                                 .for_each(|line| pb.println(format!("{name} {line}")));
                         });
                     }
                     child
                 }
-                Err(err) => error!(%err, "Cannot start command {name}."),
+                Err(err) => return Err(Error::Command { name: name.clone(), source: err }),
             };
         } else {
             cmd = match Command::new(&name)
+                .current_dir(&working_dir)
                 .args(args.to_owned())
                 .stdout(Stdio::inherit())
                 .stderr(Stdio::inherit())
@@ -84,71 +107,91 @@ pub(crate) fn run_wrapped(runner: Runner<toml::Value>) {
                 .spawn()
             {
                 Ok(child) => child,
-                Err(err) => error!(%err, "Cannot start command {name}."),
+                Err(err) => return Err(Error::Command { name: name.clone(), source: err }),
             };
         }
 
         let status = cmd.wait();
-        let exit_code = helpers::status::code(&status);
+        let success = helpers::status::success(&status)?;
+        let exit_code = helpers::status::code(&status)?;
 
-        status_array.push(status);
         debug!("Finished cmd: '{name} {}' with exit code: {:?} in {:.2?}", args.join(" "), exit_code, start.elapsed());
-    }
 
-    let status = match status_array.last() {
-        Some(status) => status,
-        None => error!("Failed to fetch final status code."),
-    };
+        if !success {
+            if failed.is_none() {
+                failed = Some(Error::Failed {
+                    task: runner.name.clone(),
+                    index: index + 1,
+                    command: script,
+                    code: exit_code,
+                });
+            }
+
+            if !continue_on_error {
+                break;
+            }
+            warn!("Command {} of {} failed, continuing", index + 1, runner.name);
+        }
+    }
 
     let cache = match &runner.maidfile.tasks[&runner.name].cache {
         Some(cache) => cache.clone(),
-        None => Cache { path: "".to_string(), target: vec![] },
+        None => Cache::default(),
     };
 
-    let exit_code = helpers::status::code(status);
-    let success = helpers::status::success(&status);
+    let success = failed.is_none();
+
+    let saved = match &runner.key {
+        Some(key) if success && !cache.target.is_empty() => match task::store::save(&working_dir, &runner.name, &cache.target, key) {
+            Ok(config) => {
+                server::cache::upload(&runner.maidfile, &config, &working_dir);
+                Some(config)
+            }
+            Err(err) => {
+                warn!(%err, "Cannot save targets to cache");
+                None
+            }
+        },
+        _ => None,
+    };
 
     if !runner.silent {
         if success {
             println!("\n{} {}", maid::colors::OK, "finished task successfully".bright_green());
-            if !cache.path.trim().is_empty() && !cache.target.is_empty() {
-                for target in cache.target {
-                    let cache_file = format!(".maid/cache/{}/target/{}", runner.name, Path::new(&target).file_name().unwrap().to_str().unwrap());
-                    match std::fs::copy(Path::new(&target), cache_file.clone()) {
-                        Ok(_) => {
-                            println!(
-                                "{} ({})",
-                                format!("saved target '{}' to cache", target).bright_magenta(),
-                                format!("{}", human_bytes(get_size(cache_file.clone()).unwrap() as f64).white())
-                            );
-                            debug!("saved target file {}", target)
-                        }
-                        Err(err) => error!(%err, target, "Cannot save target file"),
-                    };
+            if let Some(config) = &saved {
+                for target in &config.target {
+                    println!(
+                        "{} ({})",
+                        format!("saved target '{}' to cache", target).bright_magenta(),
+                        human_bytes(task::store::size(config, target) as f64).white()
+                    );
+                    debug!("saved target file {}", target)
                 }
             }
             println!("{} took {}", runner.name.white(), format!("{:.2?}", start.elapsed()).yellow());
-        } else {
-            println!("\n{} {} {}", maid::colors::FAIL, "exited with status code".bright_red(), format!("{}", exit_code).red());
+        } else if let Some(Error::Failed { index, code, .. }) = &failed {
+            println!(
+                "\n{} {} {}",
+                maid::colors::FAIL,
+                format!("command {index} exited with status code").bright_red(),
+                format!("{}", code).red()
+            );
             println!("{} took {}", runner.name.white(), format!("{:.2?}", start.elapsed()).yellow());
         }
-    } else {
-        if success {
-            if !cache.path.trim().is_empty() && !cache.target.is_empty() {
-                for target in cache.target {
-                    let cache_file = format!(".maid/cache/{}/target/{}", runner.name, Path::new(&target).file_name().unwrap().to_str().unwrap());
-                    match std::fs::copy(Path::new(&target), cache_file.clone()) {
-                        Ok(_) => println!(
-                            "{} {}{}{}",
-                            maid::colors::ADD,
-                            format!("{}", target).bright_green(),
-                            maid::colors::SEP,
-                            format!("{}", human_bytes(get_size(cache_file.clone()).unwrap() as f64).bright_cyan())
-                        ),
-                        Err(err) => warn!(%err, %target, "Cache miss"),
-                    };
-                }
-            }
+    } else if let Some(config) = &saved {
+        for target in &config.target {
+            println!(
+                "{} {}{}{}",
+                maid::colors::ADD,
+                target.to_string().bright_green(),
+                maid::colors::SEP,
+                human_bytes(task::store::size(config, target) as f64).bright_cyan()
+            );
         }
     }
+
+    match failed {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
 }
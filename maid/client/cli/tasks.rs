@@ -1,12 +1,17 @@
-use crate::{cli, parse};
-use maid::{log::prelude::*, models::client::DisplayTask, table};
-
-use inquire::Select;
+use crate::{cli, parse, task::args};
+use maid::{
+    log::prelude::*,
+    models::{client::DisplayTask, shared::ArgumentKind},
+    table, Error, Result,
+};
+
+use inquire::{Confirm, Select, Text};
+use macros_rs::{exp::ternary, fmt::string};
 use text_placeholder::Template;
 use tracing::Level;
 
-fn create_options(path: &String, remote: bool, log_level: Option<Level>) -> Vec<DisplayTask> {
-    let values = parse::merge(path);
+fn create_options(path: &String, remote: bool, log_level: Option<Level>) -> Result<Vec<DisplayTask>> {
+    let values = parse::merge(path)?;
     let mut options: Vec<DisplayTask> = Vec::new();
 
     for (key, task) in &values.tasks {
@@ -19,66 +24,95 @@ fn create_options(path: &String, remote: bool, log_level: Option<Level>) -> Vec<
             }
         }
 
+        let usage: Vec<String> = task.args.iter().flatten().map(|(name, argument)| args::usage(name, argument)).collect();
+
         if log_level.unwrap_or(Level::INFO) != Level::INFO {
             verbose = task.script.to_string()
         };
 
         let hidden = match remote {
-            true => match task.remote {
-                Some(_) => false,
-                None => true,
-            },
-            false => key.starts_with("_") || task.hide.map_or(task.remote.as_ref().map_or(false, |r| r.exclusive), |h| h),
+            true => task.remote.is_none(),
+            false => key.starts_with("_") || task.hide.map_or(task.remote.as_ref().is_some_and(|r| r.exclusive), |h| h),
         };
 
         if !hidden {
             options.push(DisplayTask {
                 name: key.to_owned(),
-                formatted: format!("{}{desc} {}", format!("{key}").truecolor(255, 165, 0), verbose.bright_blue()),
+                formatted: format!("{}{desc} {}{}", key.to_string().truecolor(255, 165, 0), ternary!(usage.is_empty(), string!(""), format!("{} ", usage.join(" ").bright_black())), verbose.bright_blue()),
             });
         }
     }
 
-    options
+    Ok(options)
+}
+
+// asks for every argument of the picked task that has no default
+fn prompt_args(path: &String, task: &str) -> Result<Vec<String>> {
+    let values = parse::merge(path)?;
+    let mut named = vec![String::from("")];
+
+    for (name, argument) in values.tasks[task].args.iter().flatten().filter(|(_, argument)| argument.default.is_none()) {
+        let message = format!("--{name}:");
+        let help = argument.description.clone().unwrap_or_default();
+
+        let value = match argument.kind {
+            ArgumentKind::Enum => Select::new(&message, argument.values.clone()).with_help_message(&help).prompt(),
+            ArgumentKind::Bool => Confirm::new(&message).with_help_message(&help).prompt().map(|value| value.to_string()),
+            _ => Text::new(&message).with_help_message(&help).prompt(),
+        };
+
+        match value {
+            Ok(value) => named.push(format!("--{name}={value}")),
+            Err(_) => return Err(Error::Argument(format!("No value given for argument '--{name}'"))),
+        }
+    }
+
+    Ok(named)
 }
 
-pub(crate) fn list_json(path: &String, args: &Vec<String>, hydrate: bool) {
-    let values = parse::merge(path);
-    let json = values.to_json();
+pub(crate) fn list_json(path: &String, args: &[String], hydrate: bool) -> Result<()> {
+    let values = parse::merge(path)?;
+    let json = values.to_json()?;
 
     if hydrate {
-        let project = parse::file::find_maidfile_root(path);
-        let table = table::create(values, args, project);
+        let project = parse::file::find_maidfile_root(path)?;
+        let table = table::create(values, args, project)?;
         let hydrated = Template::new_with_placeholder(&json, "%{", "}").fill_with_hashmap(&table);
 
         println!("{hydrated}")
     } else {
         println!("{json}")
     }
+
+    Ok(())
 }
 
-pub(crate) fn list_all(path: &String, silent: bool, log_level: Option<Level>, force: bool) {
-    let options = create_options(path, false, log_level);
+pub(crate) fn list_all(path: &String, silent: bool, log_level: Option<Level>, force: bool, jobs: usize) -> Result<()> {
+    let options = create_options(path, false, log_level)?;
 
     match Select::new("Select a task to run:", options).prompt() {
         Ok(task) => {
             debug!("Starting {}", task.name);
-            cli::exec(&String::from(task.name), &vec![String::from("")], &path, silent, false, false, log_level, force, false);
+            cli::exec(&task.name, &prompt_args(path, &task.name)?, path, silent, false, false, false, log_level, force, false, jobs)?;
         }
 
         Err(_) => println!("{}", "Aborting...".white()),
     }
+
+    Ok(())
 }
 
-pub(crate) fn list_remote(path: &String, silent: bool, log_level: Option<Level>) {
-    let options = create_options(path, true, log_level);
+pub(crate) fn list_remote(path: &String, silent: bool, log_level: Option<Level>, jobs: usize) -> Result<()> {
+    let options = create_options(path, true, log_level)?;
 
     match Select::new("Select a remote task to run:", options).prompt() {
         Ok(task) => {
             debug!("Starting {}", task.name);
-            cli::exec(&String::from(task.name), &vec![String::from("")], &path, silent, false, true, log_level, false, false);
+            cli::exec(&task.name, &prompt_args(path, &task.name)?, path, silent, false, true, false, log_level, false, false, jobs)?;
         }
 
         Err(_) => println!("{}", "Aborting...".white()),
     }
+
+    Ok(())
 }
@@ -0,0 +1,348 @@
+use crate::parse;
+
+use maid::{graph::Graph, log::prelude::*, Error, Result};
+
+use glob::Pattern;
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    WalkBuilder,
+};
+use macros_rs::fmt::string;
+use notify::RecursiveMode;
+use notify_debouncer_mini::new_debouncer;
+use tracing::Level;
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    sync::{atomic::AtomicBool, atomic::Ordering, mpsc::RecvTimeoutError, Arc},
+    time::{Duration, Instant},
+};
+
+struct Filter {
+    root: PathBuf,
+    globs: Vec<Pattern>,
+    gitignores: Vec<Gitignore>,
+}
+
+impl Filter {
+    // every .gitignore in the project, each rooted at its own directory and the deepest first,
+    // directories already ignored are not searched
+    fn new(root: PathBuf, globs: Vec<Pattern>) -> Self {
+        let walker = WalkBuilder::new(&root)
+            .hidden(false)
+            .require_git(false)
+            .filter_entry(|entry| !internal(Path::new(entry.file_name())))
+            .build();
+
+        let mut gitignores: Vec<Gitignore> = vec![];
+
+        for entry in walker.flatten().filter(|entry| entry.file_name() == ".gitignore") {
+            let mut builder = GitignoreBuilder::new(entry.path().parent().unwrap_or(&root));
+
+            if let Some(err) = builder.add(entry.path()) {
+                trace!(%err, "unusable {}", entry.path().display());
+            }
+
+            match builder.build() {
+                Ok(gitignore) => gitignores.push(gitignore),
+                Err(err) => trace!(%err, "unusable {}", entry.path().display()),
+            }
+        }
+
+        gitignores.sort_by_key(|gitignore| std::cmp::Reverse(gitignore.path().components().count()));
+
+        Self { root, globs, gitignores }
+    }
+
+    // a nested .gitignore overrides its parents, the same as git
+    fn ignored(&self, path: &Path) -> bool {
+        self.gitignores
+            .iter()
+            .filter(|gitignore| path.starts_with(gitignore.path()))
+            .map(|gitignore| gitignore.matched_path_or_any_parents(path, path.is_dir()))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore())
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            // only directories passed to --watch live outside the project, they were asked for explicitly
+            Err(_) => return !internal(path),
+        };
+
+        if internal(relative) {
+            return false;
+        }
+
+        if self.ignored(path) {
+            return false;
+        }
+
+        self.globs.is_empty() || self.globs.iter().any(|pattern| pattern.matches_path(relative))
+    }
+}
+
+fn internal(path: &Path) -> bool { path.components().any(|item| item.as_os_str() == ".maid" || item.as_os_str() == ".git") }
+
+// whether maid owns the terminal, only then it is handed to each run
+#[cfg(unix)]
+fn interactive() -> bool { unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() } }
+
+#[cfg(not(unix))]
+fn interactive() -> bool { false }
+
+// makes a process group the terminal's foreground, a background group doing so is stopped by SIGTTOU unless it ignores it
+#[cfg(unix)]
+fn foreground(group: libc::pid_t) {
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, group);
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+// takes the terminal back once a run is over
+fn reclaim(terminal: bool) {
+    #[cfg(unix)]
+    if terminal {
+        foreground(unsafe { libc::getpgrp() });
+    }
+}
+
+// re-invokes maid for the task in a process group of its own, so a run can be killed along with everything
+// it started. the group is given the terminal, so interactive tasks can still read from it
+fn start(task: &[String], path: &str, log_level: Option<Level>, force: bool, jobs: usize, terminal: bool) -> Result<Child> {
+    let exe = std::env::current_exe().map_err(|err| Error::io("Unable to locate maid executable", err))?;
+    let mut command = Command::new(exe);
+
+    command.args(["--path", path, "--jobs", &jobs.to_string()]);
+
+    match log_level {
+        None => command.arg("-q"),
+        Some(Level::DEBUG) => command.arg("-v"),
+        Some(Level::TRACE) => command.arg("-vv"),
+        Some(_) => &mut command,
+    };
+
+    if force {
+        command.arg("--force");
+    }
+
+    // task arguments may share a name with maid's flags
+    command.arg("--").args(task);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+
+        // both sides switch the foreground, so the run cannot read before it owns the terminal
+        if terminal {
+            unsafe {
+                command.pre_exec(|| {
+                    foreground(libc::getpid());
+                    Ok(())
+                })
+            };
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = terminal;
+
+    let child = command.spawn().map_err(|err| Error::Command { name: string!("maid"), source: err })?;
+
+    #[cfg(unix)]
+    if terminal {
+        foreground(child.id() as libc::pid_t);
+    }
+
+    Ok(child)
+}
+
+// a run in the foreground gets ctrl-c instead of maid, so dying from it ends watching too
+fn cancelled(status: &std::process::ExitStatus) -> bool {
+    #[cfg(unix)]
+    return std::os::unix::process::ExitStatusExt::signal(status) == Some(libc::SIGINT);
+
+    #[cfg(not(unix))]
+    return false;
+}
+
+fn stop(child: &mut Child, terminal: bool) {
+    kill(child);
+    reclaim(terminal);
+}
+
+fn kill(child: &mut Child) {
+    if let Ok(Some(_)) = child.try_wait() {
+        return;
+    }
+
+    #[cfg(unix)]
+    {
+        let group = -(child.id() as i32);
+        unsafe { libc::kill(group, libc::SIGTERM) };
+
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        unsafe { libc::kill(group, libc::SIGKILL) };
+    }
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn clear() { print!("\x1B[2J\x1B[3J\x1B[H") }
+
+pub(crate) fn run(task: &[String], path: &String, paths: &[String], log_level: Option<Level>, force: bool, jobs: usize) -> Result<()> {
+    let name = match task.first().map(|name| name.trim()) {
+        Some(name) if !name.is_empty() => name,
+        _ => return Err(Error::Argument(string!("Watch mode needs a task, try `maid <task> --watch [paths]`"))),
+    };
+
+    let values = parse::merge(path)?;
+    let project_root = parse::file::find_maidfile_root(path)?;
+    let root = project_root.canonicalize().map_err(|err| Error::io("Unable to resolve project directory", err))?;
+
+    if !values.tasks.contains_key(name) {
+        return Err(Error::MissingTask(string!(name)));
+    }
+
+    let graph = Graph::new(&values, name)?;
+    let mut globs: Vec<Pattern> = vec![];
+
+    for node in graph.nodes() {
+        for item in values.tasks[&node.name].watch.iter().flatten() {
+            match Pattern::new(item) {
+                Ok(pattern) => globs.push(pattern),
+                Err(err) => return Err(Error::Parse(format!("Invalid watch pattern '{item}' in task '{}': {err}", node.name))),
+            }
+        }
+    }
+
+    let mut targets: Vec<PathBuf> = vec![];
+
+    for item in paths {
+        match Path::new(item).canonicalize() {
+            Ok(target) => targets.push(target),
+            Err(err) => return Err(Error::io(format!("Unable to watch {item}"), err)),
+        }
+    }
+
+    if targets.is_empty() {
+        targets.push(root.clone());
+    }
+
+    let filter = Filter::new(root, globs);
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut debouncer = new_debouncer(Duration::from_millis(300), tx).map_err(|err| Error::Status(format!("Unable to start watcher: {err}")))?;
+
+    for target in &targets {
+        if let Err(err) = debouncer.watcher().watch(target, RecursiveMode::Recursive) {
+            return Err(Error::Status(format!("Unable to watch {}: {err}", target.display())));
+        }
+    }
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        if let Err(err) = signal_hook::flag::register(signal, Arc::clone(&interrupted)) {
+            warn!(%err, "Unable to handle interrupt signal");
+        }
+    }
+
+    let watching = targets.iter().map(|target| target.display().to_string()).collect::<Vec<_>>().join(", ");
+    let terminal = interactive();
+    let mut child = start(task, path, log_level, force, jobs, terminal)?;
+    let mut running = true;
+
+    loop {
+        if interrupted.load(Ordering::Relaxed) {
+            stop(&mut child, terminal);
+            return Ok(());
+        }
+
+        if running {
+            if let Ok(Some(status)) = child.try_wait() {
+                running = false;
+                reclaim(terminal);
+
+                if cancelled(&status) {
+                    return Ok(());
+                }
+
+                match status.success() {
+                    true => println!("\n{} {}", maid::colors::OK, format!("watching {watching} for changes").bright_green()),
+                    false => println!("\n{} {}", maid::colors::FAIL, format!("run failed, watching {watching} for changes").bright_red()),
+                }
+            }
+        }
+
+        let events = match rx.recv_timeout(Duration::from_millis(200)) {
+            Ok(Ok(events)) => events,
+            Ok(Err(err)) => {
+                warn!(%err, "Watcher error");
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Err(Error::Status(string!("File watcher stopped unexpectedly"))),
+        };
+
+        let changed: Vec<PathBuf> = events.into_iter().map(|event| event.path).filter(|path| filter.matches(path)).collect();
+
+        if let Some(first) = changed.first() {
+            stop(&mut child, terminal);
+            clear();
+
+            let file = first.strip_prefix(&filter.root).unwrap_or(first).display().to_string();
+            let more = match changed.len() {
+                1 => string!(""),
+                count => format!(" (+{} more)", count - 1),
+            };
+
+            println!("{} {}{}\n", maid::colors::ARROW, format!("{file} changed, restarting {name}").yellow(), more.white());
+
+            child = start(task, path, log_level, force, jobs, terminal)?;
+            running = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_keeps_explicit_paths_outside_the_project() {
+        let root = std::env::temp_dir().join(format!("maid-watch-{}", uuid::Uuid::new_v4().simple()));
+
+        std::fs::create_dir_all(root.join("src/generated")).unwrap();
+        std::fs::create_dir_all(root.join("target/debug")).unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        std::fs::write(root.join("src/.gitignore"), "generated/\n!keep.log\n").unwrap();
+
+        let filter = Filter::new(root.clone(), vec![Pattern::new("src/*").unwrap(), Pattern::new("src/**/*.rs").unwrap()]);
+
+        assert!(filter.matches(&root.join("src/main.rs")));
+        assert!(!filter.matches(&root.join("README.md")));
+        assert!(!filter.matches(&root.join(".maid/cache/tasks/build")));
+        assert!(filter.matches(Path::new("/elsewhere/config.toml")));
+        assert!(!filter.matches(Path::new("/elsewhere/.git/HEAD")));
+
+        // ignored by the root .gitignore, by the nested one, and let back in by the nested one
+        assert!(!filter.matches(&root.join("src/debug.log")));
+        assert!(!filter.matches(&root.join("src/generated/schema.rs")));
+        assert!(filter.matches(&root.join("src/keep.log")));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -2,5 +2,8 @@ use global_placeholders::init;
 
 pub(crate) fn init() {
     init!("maid.temp_dir", ".maid/temp");
-    init!("maid.cache_dir", ".maid/cache/{}/target");
+    init!("maid.transfers_dir", ".maid/temp/transfers");
+    init!("maid.cache_dir", ".maid/cache/tasks/{}");
+    init!("maid.objects_dir", ".maid/cache/objects");
+    init!("maid.update_url", "https://api.maid.ci");
 }
@@ -10,9 +10,8 @@ use maid::log::{
     verbose::{InfoLevel, Verbosity},
 };
 
-use clap::{Parser, ValueEnum};
+use clap::{CommandFactory, Parser, ValueEnum};
 use macros_rs::fmt::str;
-use std::path::Path;
 
 macro_rules! dispatch {
     ($cli:expr, { $($flag:ident => $func:expr),+ $(,)? }) => {$(
@@ -26,7 +25,7 @@ macro_rules! dispatch {
 #[command(version = str!(cli::get_version(false)))]
 #[clap(disable_help_flag = true, disable_help_subcommand = true)]
 struct Cli {
-    /// Run a task defined in Maidfile
+    /// Run a task defined in Maidfile, arguments after `--` go to the task even when named like a flag here
     #[arg(default_value = "", hide_default_value = true)]
     task: Vec<String>,
 
@@ -38,10 +37,22 @@ struct Cli {
     #[arg(short, long)]
     force: bool,
 
-    /// Switch Maid to server mode
+    /// Maximum number of dependencies to run at once
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Show why a task's build cache would miss
+    #[arg(long, value_name = "TASK", group = "commands")]
+    explain_cache: Option<String>,
+
+    /// Switch Maid to server mode (attach <id>, logs <id> and jobs manage server jobs)
     #[arg(short, long, visible_alias = "online")]
     remote: bool,
 
+    /// Start a remote task as a server job and return its id without waiting
+    #[arg(short, long, requires = "remote")]
+    detach: bool,
+
     /// Clear build cache
     #[arg(short = 'C', long, visible_alias = "purge", group = "commands")]
     clean_cache: bool,
@@ -54,9 +65,9 @@ struct Cli {
     #[arg(short, long, visible_alias = "tasks", visible_alias = "ls", group = "commands")]
     list: bool,
 
-    /// Watch for changes in specified path
-    #[arg(short = 'W', long)]
-    watch: Option<String>,
+    /// Rerun the task when files change (project directory by default)
+    #[arg(short = 'W', long, value_name = "PATHS", num_args = 0..)]
+    watch: Option<Vec<String>>,
 
     /// View Maid health (server health if enabled)
     #[arg(short = 'H', long, group = "commands")]
@@ -98,28 +109,67 @@ enum Project {
     Env,
 }
 
+// named task arguments (--env=prod) are not maid flags, keep them away from clap. arguments named
+// like one of maid's flags are only passed on after `--`, so `maid deploy -- --path=/srv` reaches the task
+fn split_args() -> (Vec<String>, Vec<String>) {
+    let mut command = Cli::command();
+    command.build();
+
+    let flags: Vec<&str> = command.get_arguments().flat_map(|arg| arg.get_long_and_visible_aliases().unwrap_or_default()).collect();
+    let (mut argv, mut named): (Vec<String>, Vec<String>) = (vec![], vec![]);
+    let mut args = std::env::args();
+
+    argv.extend(args.next());
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            argv.push(arg);
+            argv.extend(args.by_ref());
+            break;
+        }
+
+        match arg.strip_prefix("--").map(|flag| flag.split('=').next().unwrap_or_default()) {
+            Some(name) if !name.is_empty() && !flags.contains(&name) => named.push(arg),
+            _ => argv.push(arg),
+        }
+    }
+
+    (argv, named)
+}
+
 fn main() {
-    let cli = Cli::parse();
+    let (argv, named) = split_args();
+    let mut cli = Cli::parse_from(argv);
+    cli.task.extend(named);
+
     let log_layer = MaidFormatLayer::new();
 
     globals::init();
-
     tracing_subscriber::registry().with(cli.verbose.log_level_filter()).with(log_layer).init();
 
+    if let Err(err) = run(cli) {
+        tracing::error!("{err}");
+        std::process::exit(err.code());
+    }
+}
+
+fn run(cli: Cli) -> maid::Result<()> {
+    let jobs = cli.jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+
     dispatch!(cli, {
         init => cli::dispatch::init(),
         health => server::cli::connect(&cli.path),
         health => match cli.remote {
             true => server::cli::connect(&cli.path),
-            false => {}, // improve health command for later
+            false => Ok(()), // improve health command for later
         },
         clean_cache => match cli.remote {
             true => server::cli::connect(&cli.path),
-            false => cli::dispatch::clean(),
+            false => cli::dispatch::clean(&cli.path),
         },
         list => match cli.remote {
-            true => cli::tasks::list_remote(&cli.path, cli.verbose.is_silent(), cli.verbose.log_level()),
-            false => cli::tasks::list_all(&cli.path, cli.verbose.is_silent(), cli.verbose.log_level(), cli.force),
+            true => cli::tasks::list_remote(&cli.path, cli.verbose.is_silent(), cli.verbose.log_level(), jobs),
+            false => cli::tasks::list_all(&cli.path, cli.verbose.is_silent(), cli.verbose.log_level(), cli.force, jobs),
         }
     });
 
@@ -130,17 +180,33 @@ fn main() {
         };
     }
 
+    if let Some(task) = cli.explain_cache {
+        let args = std::iter::once(task.clone()).chain(cli.task.into_iter().filter(|arg| !arg.is_empty())).collect::<Vec<_>>();
+        return cli::explain_cache(&cli.path, &task, &args);
+    }
+
     if let Some(system) = cli.system {
         return match system {
             System::CheckUpdates => cli::dispatch::check_update(),
-            System::Upgrade => {} // add upgrader
+            System::Upgrade => cli::dispatch::upgrade(cli.force),
             System::Json => cli::tasks::list_json(&cli.path, &cli.task, false),
             System::JsonHydrated => cli::tasks::list_json(&cli.path, &cli.task, true),
         };
     }
 
-    if let Some(path) = cli.watch {
-        return cli::dispatch::watch(Path::new(&path)); // migrate watch path into executer below
+    if let Some(paths) = cli.watch {
+        return cli::watch::run(&cli.task, &cli.path, &paths, cli.verbose.log_level(), cli.force, jobs);
+    }
+
+    if cli.remote {
+        let id = cli.task.get(1).map(|id| id.trim()).unwrap_or_default();
+
+        match cli.task[0].trim() {
+            "attach" => return server::cli::attach(&cli.path, id, true),
+            "logs" => return server::cli::attach(&cli.path, id, false),
+            "jobs" => return server::cli::jobs(&cli.path),
+            _ => {}
+        }
     }
 
     cli::exec(
@@ -150,8 +216,12 @@ fn main() {
         cli.verbose.is_silent(),
         false,
         cli.remote,
+        cli.detach,
         cli.verbose.log_level(),
         cli.force,
         false,
-    )
+        jobs,
+    )?;
+
+    Ok(())
 }
@@ -1,8 +1,8 @@
 use maid::log::prelude::*;
-use maid::models::shared::Maidfile;
+use maid::{models::shared::Maidfile, Error, Result};
 
 use macros_rs::{exp::then, fmt::string};
-use std::{env, fs, io::Result, path::Path, path::PathBuf};
+use std::{env, fs, io, path::Path, path::PathBuf};
 use toml::Value;
 
 macro_rules! create_path {
@@ -20,15 +20,10 @@ struct Filesystem {
     is_file: bool,
 }
 
-fn working_dir() -> PathBuf {
-    match env::current_dir() {
-        Ok(path) => path,
-        Err(_) => error!("Unable to find current working dir"),
-    }
-}
+fn working_dir() -> Result<PathBuf> { env::current_dir().map_err(|err| Error::io("Unable to find current working dir", err)) }
 
 #[allow(unused_variables)]
-fn find_path(path: &Path, file_name: &str, kind: &str) -> Result<Option<fs::DirEntry>> {
+fn find_path(path: &Path, file_name: &str, kind: &str) -> io::Result<Option<fs::DirEntry>> {
     #[cfg(target_os = "linux")]
     {
         for entry in fs::read_dir(path)? {
@@ -48,38 +43,40 @@ fn find_path(path: &Path, file_name: &str, kind: &str) -> Result<Option<fs::DirE
     }
 }
 
-fn find_file(starting_directory: &Path, file_name: &String) -> Option<PathBuf> {
+fn find_file(starting_directory: &Path, file_name: &String) -> Result<Option<PathBuf>> {
     let mut path: PathBuf = starting_directory.into();
-    let find_kind = |kind: &str, mut inner: PathBuf| -> Filesystem {
+    let current = working_dir()?;
+
+    let find_kind = |kind: &str, mut inner: PathBuf| -> Result<Filesystem> {
         let file_path = create_path!(file_name, kind);
-        then!(working_dir() != starting_directory, inner.pop());
+        then!(current != starting_directory, inner.pop());
 
-        match find_path(starting_directory, file_name, kind).unwrap() {
+        match find_path(starting_directory, file_name, kind).map_err(|err| Error::io("Unable to search for Maidfile", err))? {
             Some(file) => inner.push(file.path()),
             None => inner.push(file_path),
         }
 
-        Filesystem {
+        Ok(Filesystem {
             path: Some(inner.clone()),
             is_file: inner.is_file(),
-        }
+        })
     };
 
     loop {
-        for extension in vec!["", "toml", "yaml", "yml", "json", "hcl"].iter() {
-            let kind = find_kind(extension, path.clone());
-            then!(kind.is_file, return kind.path);
+        for extension in ["", "toml", "yaml", "yml", "json", "hcl"].iter() {
+            let kind = find_kind(extension, path.clone())?;
+            then!(kind.is_file, return Ok(kind.path));
         }
         then!(!path.pop(), break);
     }
 
-    return None;
+    Ok(None)
 }
 
-fn read_file(path: PathBuf, kind: &str) -> Maidfile<Value> {
+fn read_file(path: PathBuf, kind: &str) -> Result<Maidfile<Value>> {
     let contents = match fs::read_to_string(&path) {
         Ok(contents) => contents,
-        Err(_) => error!("Cannot find Maidfile. Does it exist?"),
+        Err(_) => return Err(Error::NotFound(string!("Cannot find Maidfile. Does it exist?"))),
     };
 
     let result = match kind {
@@ -87,18 +84,18 @@ fn read_file(path: PathBuf, kind: &str) -> Maidfile<Value> {
         "json" => serde_json::from_str(&contents).map_err(|err| string!(err)),
         "hcl" => hcl::from_str(&contents).map_err(|err| string!(err)),
         "yaml" | "yml" => serde_yaml::from_str(&contents).map_err(|err| string!(err)),
-        _ => error!("Invalid format, cannot read Maidfile"),
+        _ => return Err(Error::Parse(string!("Invalid format, cannot read Maidfile"))),
     };
 
     match result {
-        Ok(parsed) => parsed,
-        Err(err) => error!("Cannot read Maidfile.\n{}", err.white()),
+        Ok(parsed) => Ok(parsed),
+        Err(err) => Err(Error::Parse(format!("Cannot read Maidfile.\n{}", err.white()))),
     }
 }
 
-pub fn read_maidfile_with_error(filename: &String, error: &str) -> Maidfile<Value> {
+pub fn read_maidfile_with_error(filename: &String, error: &str) -> Result<Maidfile<Value>> {
     match env::current_dir() {
-        Ok(path) => match find_file(&path, &filename) {
+        Ok(path) => match find_file(&path, filename)? {
             Some(path) => {
                 let extension = path.extension().and_then(|s| s.to_str());
                 debug!(path = path.display().to_string(), kind = extension, "Found tasks");
@@ -108,24 +105,24 @@ pub fn read_maidfile_with_error(filename: &String, error: &str) -> Maidfile<Valu
                     _ => read_file(path, "toml"),
                 }
             }
-            None => error!("{error}"),
+            None => Err(Error::NotFound(string!(error))),
         },
-        Err(err) => error!(%err, "Home directory could not found"),
+        Err(err) => Err(Error::io("Home directory could not found", err)),
     }
 }
 
-pub fn find_maidfile_root(filename: &String) -> PathBuf {
+pub fn find_maidfile_root(filename: &String) -> Result<PathBuf> {
     match env::current_dir() {
-        Ok(path) => match find_file(&path, &filename) {
+        Ok(path) => match find_file(&path, filename)? {
             Some(mut path) => {
                 path.pop();
                 debug!("Found project path: {}", path.display());
-                return path;
+                Ok(path)
             }
-            None => error!("Cannot find project root."),
+            None => Err(Error::NotFound(string!("Cannot find project root."))),
         },
-        Err(err) => error!(%err, "Home directory could not found"),
+        Err(err) => Err(Error::io("Home directory could not found", err)),
     }
 }
 
-pub fn read_maidfile(filename: &String) -> Maidfile<Value> { read_maidfile_with_error(filename, "Cannot find maidfile. Does it exist?") }
+pub fn read_maidfile(filename: &String) -> Result<Maidfile<Value>> { read_maidfile_with_error(filename, "Cannot find maidfile. Does it exist?") }
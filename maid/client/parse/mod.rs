@@ -1,20 +1,20 @@
 pub mod file;
 pub mod import;
 
-use maid::log::prelude::*;
 use maid::models::shared::Maidfile;
+use maid::{Error, Result};
 use toml::Value;
 
-pub(crate) fn merge(path: &String) -> Maidfile<Value> {
-    let mut values = file::read_maidfile(path);
-    let imported_values = import::push(values.import.clone());
+pub(crate) fn merge(path: &String) -> Result<Maidfile<Value>> {
+    let mut values = file::read_maidfile(path)?;
+    let imported_values = import::push(values.import.clone())?;
 
     for import in imported_values.iter() {
-        values = match merge_struct::merge(&values, &import) {
+        values = match merge_struct::merge(&values, import) {
             Ok(merge) => merge,
-            Err(err) => error!(%err, "Unable to import tasks"),
+            Err(err) => return Err(Error::Parse(format!("Unable to import tasks: {err}"))),
         };
     }
 
-    return values;
+    Ok(values)
 }
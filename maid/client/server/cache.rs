@@ -0,0 +1,151 @@
+use crate::{server, task};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use maid::{
+    log::prelude::*,
+    models::{client::CacheConfig, client::EntryKind, shared::Maidfile},
+};
+
+use reqwest::{blocking::Client, StatusCode};
+use std::{collections::BTreeSet, io::Read, path::Path, time::Duration};
+use tar::{Archive, Builder, Header};
+use toml::Value;
+
+const MANIFEST: &str = "manifest.toml";
+
+fn client() -> Client { Client::builder().timeout(Duration::from_secs(300)).connect_timeout(Duration::from_secs(3)).build().unwrap_or_default() }
+
+fn endpoint(values: &Maidfile<Value>, hash: &str) -> Option<(String, String)> {
+    let address = server::parse::address(values);
+    let token = server::parse::token(values);
+
+    match address.is_empty() {
+        true => None,
+        false => Some((format!("{address}/api/cache/{hash}"), format!("Bearer {token}"))),
+    }
+}
+
+fn pack(dir: &Path, config: &CacheConfig) -> std::io::Result<Vec<u8>> {
+    let mut tar = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let manifest = toml::to_string(config).map_err(std::io::Error::other)?;
+
+    let mut header = Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, MANIFEST, manifest.as_bytes())?;
+
+    let objects: BTreeSet<&str> = config.entries.iter().filter(|entry| entry.kind == EntryKind::File).filter_map(|entry| entry.hash.as_deref()).collect();
+
+    for hash in objects {
+        tar.append_path_with_name(task::store::object_path(dir, hash), format!("objects/{hash}"))?;
+    }
+
+    tar.into_inner()?.finish()
+}
+
+fn unpack(dir: &Path, bytes: &[u8]) -> std::io::Result<CacheConfig> {
+    let mut archive = Archive::new(GzDecoder::new(bytes));
+    let mut manifest: Option<CacheConfig> = None;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+
+        if path == MANIFEST {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            manifest = Some(toml::from_str(&contents).map_err(std::io::Error::other)?);
+        } else if let Some(hash) = path.strip_prefix("objects/") {
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                warn!("Ignoring unexpected cache object '{path}'");
+                continue;
+            }
+            task::store::insert_object(dir, hash, &mut entry)?;
+        }
+    }
+
+    manifest.ok_or_else(|| std::io::Error::other("remote artifact has no manifest"))
+}
+
+pub(crate) fn fetch(values: &Maidfile<Value>, task: &str, hash: &str, dir: &Path) -> Option<CacheConfig> {
+    let (url, token) = endpoint(values, hash)?;
+
+    let response = match client().get(&url).header("Authorization", token).send() {
+        Ok(response) => response,
+        Err(err) => {
+            warn!(%err, "Unable to reach remote cache");
+            return None;
+        }
+    };
+
+    match response.status() {
+        StatusCode::OK => {}
+        StatusCode::NOT_FOUND => {
+            debug!("remote cache miss for {task} -> {hash}");
+            return None;
+        }
+        status => {
+            warn!(%status, "Remote cache refused the request");
+            return None;
+        }
+    }
+
+    // anyone allowed to write the cache could have put this here, nothing of it is trusted
+    let targets = values.tasks.get(task).and_then(|task| task.cache.as_ref()).map(|cache| cache.target.clone()).unwrap_or_default();
+
+    let config = match response.bytes().map_err(std::io::Error::other).and_then(|bytes| unpack(dir, &bytes)) {
+        Ok(config) if config.hash != hash => {
+            warn!("Remote cache returned an artifact for a different key");
+            return None;
+        }
+        Ok(config) if config.target != targets => {
+            warn!("Remote cache artifact restores different targets than the task caches, ignoring it");
+            return None;
+        }
+        Ok(config) => match task::store::contained(&config) {
+            Ok(_) => config,
+            Err(err) => {
+                warn!(%err, "Remote cache artifact is unsafe, ignoring it");
+                return None;
+            }
+        },
+        Err(err) => {
+            warn!(%err, "Unable to read remote cache artifact");
+            return None;
+        }
+    };
+
+    match task::store::write(dir, task, &config) {
+        Ok(_) => debug!("downloaded remote cache for {task} -> {hash}"),
+        Err(err) => warn!(%err, "Cannot write cache config"),
+    };
+
+    Some(config)
+}
+
+pub(crate) fn upload(values: &Maidfile<Value>, config: &CacheConfig, dir: &Path) {
+    let (url, token) = match endpoint(values, &config.hash) {
+        Some(endpoint) => endpoint,
+        None => return,
+    };
+
+    let client = client();
+
+    match client.head(&url).header("Authorization", &token).send() {
+        Ok(response) if response.status() == StatusCode::OK => return debug!("remote cache already has {}", config.hash),
+        Ok(_) => {}
+        Err(err) => return warn!(%err, "Unable to reach remote cache"),
+    };
+
+    let body = match pack(dir, config) {
+        Ok(body) => body,
+        Err(err) => return warn!(%err, "Unable to create remote cache artifact"),
+    };
+
+    match client.put(&url).header("Authorization", &token).body(body).send() {
+        Ok(response) if response.status().is_success() => debug!("uploaded remote cache {}", config.hash),
+        Ok(response) => warn!(status = %response.status(), "Remote cache refused the upload"),
+        Err(err) => warn!(%err, "Unable to upload to remote cache"),
+    };
+}
@@ -2,88 +2,137 @@ use crate::parse;
 use crate::server;
 
 use maid::{
-    helpers,
     log::prelude::*,
     models::{
-        client::{ConnectionData, ConnectionInfo, Kind, Level, Task, Websocket},
-        shared::Maidfile,
+        client::{CacheEntry, EntryKind, Task},
+        shared::{Maidfile, Pull},
     },
+    protocol::{self, ConnectionData, ConnectionInfo, Handshake, JobStatus, JobSummary, Kind, Level, Manifest, Missing, Phase, Request, Response, Step, Transfer},
+    Error, Result,
 };
 
-use macros_rs::fmt::fmtstr;
+use human_bytes::human_bytes;
+use macros_rs::fmt::{fmtstr, string};
 use reqwest::blocking::Client;
 use toml::Value;
 use tungstenite::protocol::frame::{coding::CloseCode::Normal, CloseFrame};
-use tungstenite::{client::connect_with_config, client::IntoClientRequest, protocol::WebSocketConfig, Message};
+use tungstenite::{client::connect_with_config, client::IntoClientRequest, stream::MaybeTlsStream, Message, WebSocket};
 
-fn health(client: Client, values: Maidfile<Value>) -> server::api::health::Route {
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    net::TcpStream,
+    path::Path,
+    time::Duration,
+};
+
+// how often a session that dropped while moving artifacts is reopened
+const ATTEMPTS: usize = 3;
+
+enum Stage {
+    Connect,
+    Upload,
+    Run,
+    Download(String),
+}
+
+// how a session ended, detached runs keep going on the server
+enum Ended {
+    Exited(Option<i64>),
+    Detached(String),
+}
+
+impl Stage {
+    // only transfers pick up where they stopped, a dropped run is not started twice
+    fn resumable(&self) -> bool { matches!(self, Stage::Upload | Stage::Download(_)) }
+}
+
+fn health(client: Client, values: Maidfile<Value>) -> Result<server::api::health::Route> {
     let address = server::parse::address(&values);
     let token = server::parse::token(&values);
 
     let response = match client.get(fmtstr!("{address}/api/health")).header("Authorization", fmtstr!("Bearer {token}")).send() {
         Ok(res) => res,
-        Err(err) => error!(%err, "Unable to connect to the maid server. Is it up?"),
+        Err(err) => return Err(Error::Remote(format!("Unable to connect to the maid server. Is it up? ({err})"))),
     };
 
     let body = match response.json::<server::api::health::Route>() {
         Ok(body) => body,
-        Err(err) => error!(%err, "Unable to connect to the maid server. Is the token correct?"),
+        Err(err) => return Err(Error::Remote(format!("Unable to connect to the maid server. Is the token correct? ({err})"))),
     };
 
-    return body;
+    Ok(body)
 }
 
-pub fn connect(path: &String) {
-    let values = parse::merge(path);
+// both sides open with a handshake, a server that answers with anything else predates it
+fn handshake<S: Read + Write>(socket: &mut WebSocket<S>) -> Result<()> {
+    let outdated = || Error::Remote(format!("The maid server did not answer the protocol v{} handshake, it is likely older than this client", protocol::VERSION));
+    let hello = serde_json::to_string(&Handshake::new()).map_err(|err| Error::Remote(format!("Unable to encode handshake: {err}")))?;
+
+    socket.send(Message::Text(hello)).map_err(|err| Error::Remote(format!("Unable to send handshake: {err}")))?;
+
+    loop {
+        let text = match socket.read() {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) | Err(_) => return Err(outdated()),
+            Ok(_) => continue,
+        };
+
+        if let Ok(peer) = serde_json::from_str::<Handshake>(&text) {
+            return match peer.mismatch("server") {
+                Some(reason) => Err(Error::Remote(reason)),
+                None => {
+                    debug!("server speaks protocol v{} (maid {}, capabilities: {:?})", peer.protocol, peer.version, peer.capabilities);
+                    Ok(())
+                }
+            };
+        }
+
+        // a rejecting server explains itself before closing, older ones greet without a handshake
+        match serde_json::from_str::<Response>(&text) {
+            Ok(Response { kind: Kind::Message, level, message, .. }) => crate::log!(level, "{}", message.unwrap_or_default()),
+            Ok(Response { kind: Kind::Done, .. }) => return Err(Error::Remote(string!("The maid server refused the connection"))),
+            _ => return Err(outdated()),
+        }
+    }
+}
+
+pub fn connect(path: &String) -> Result<()> {
+    let values = parse::merge(path)?;
     let client = Client::new();
-    let body = health(client, values);
+    let body = health(client, values)?;
 
     println!(
-        "{}\n{}\n{}\n{}",
+        "{}\n {}: {}\n {}: {}\n {}: {}",
         "Server Info".green().bold(),
-        format!(" {}: {}", "- Version".white(), body.version.data.color(body.version.hue)),
-        format!(" {}: {}", "- Platform".white(), body.platform.data.color(body.platform.hue)),
-        format!(" {}: {}", "- Engine".white(), body.engine.data.color(body.engine.hue)),
+        "- Version".white(),
+        body.version.data.color(body.version.hue),
+        "- Platform".white(),
+        body.platform.data.color(body.platform.hue),
+        "- Engine".white(),
+        body.engine.data.color(body.engine.hue),
     );
 
     println!(
-        "{}\n{}\n{}\n{}",
+        "{}\n {}: {}\n {}: {}\n {}: {}",
         "Server Status".green().bold(),
-        format!(" {}: {}", "- Uptime".white(), body.status.uptime.data.color(body.status.uptime.hue)),
-        format!(" {}: {}", "- Healthy".white(), body.status.healthy.data.color(body.status.healthy.hue)),
-        format!(" {}: {}", "- Containers".white(), format!("{:?}", body.status.containers.data).color(body.status.containers.hue)),
+        "- Uptime".white(),
+        body.status.uptime.data.color(body.status.uptime.hue),
+        "- Healthy".white(),
+        body.status.healthy.data.color(body.status.healthy.hue),
+        "- Containers".white(),
+        format!("{:?}", body.status.containers.data).color(body.status.containers.hue),
     );
+
+    Ok(())
 }
 
-pub fn remote(task: Task<Value>) {
-    let mut script: Vec<&str> = vec![];
-
-    if task.script.is_str() {
-        match task.script.as_str() {
-            Some(cmd) => script.push(cmd),
-            None => error!("Unable to parse Maidfile. Missing string value."),
-        };
-    } else if task.script.is_array() {
-        match IntoIterator::into_iter(match task.script.as_array() {
-            Some(iter) => iter,
-            None => error!("Unable to parse Maidfile. Missing array value."),
-        }) {
-            mut iter => loop {
-                match Iterator::next(&mut iter) {
-                    Some(val) => match val.as_str() {
-                        Some(cmd) => script.push(cmd),
-                        None => error!("Unable to parse Maidfile. Missing string value."),
-                    },
-                    None => break,
-                };
-            },
-        }
-    } else {
-        helpers::status::error(task.script.type_str())
-    }
+pub fn remote(task: Task<Value>, detach: bool) -> Result<()> {
+    // the server reads the script from the Maidfile, a malformed one is still caught before connecting
+    crate::cli::script::lines(&task.script)?;
 
     let client = Client::new();
-    let body = health(client, task.maidfile.clone());
+    let body = health(client, task.maidfile.clone())?;
     let (_, websocket, token, host, port) = server::parse::all(task.maidfile.clone());
 
     crate::log!(Level::Info, "connecting to {host}:{port}");
@@ -94,76 +143,302 @@ pub fn remote(task: Task<Value>) {
         crate::log!(Level::Warning, "failed to connect");
     }
 
-    let websocket_config = WebSocketConfig {
-        max_frame_size: Some(314572800),
-        ..Default::default()
-    };
-
-    let mut request = websocket.into_client_request().expect("Can't connect");
-    request.headers_mut().insert("Authorization", fmtstr!("Bearer {token}").parse().unwrap());
-
-    let (mut socket, response) = connect_with_config(request, Some(websocket_config), 3).expect("Can't connect");
-    debug!("response code: {}", response.status());
-
-    let connection_data = ConnectionData {
+    let request = Request::Run(Box::new(ConnectionData {
         info: ConnectionInfo {
             name: task.name.clone(),
             args: task.args.clone(),
             remote: task.remote.clone().unwrap(),
-            script: script.clone().iter().map(|&s| s.to_string()).collect(),
+            detach,
         },
         maidfile: task.maidfile.clone(),
+    }));
+
+    let remote = task.remote.unwrap();
+    let entries = match server::file::manifest(&remote.push) {
+        Ok(entries) => entries,
+        Err(err) => return Err(Error::io("Unable to hash push files", err)),
+    };
+
+    match drive(&websocket, &token, &request, &entries, &remote.pull)? {
+        Ended::Exited(code) => report(task.name, code),
+        Ended::Detached(id) => {
+            println!("\n{} {} {}", maid::colors::OK, "started job".bright_green(), id.bold());
+            println!("{}", format!("follow it with `maid --remote attach {id}`, or see its output with `maid --remote logs {id}`").white());
+            Ok(())
+        }
+    }
+}
+
+/// Follows a server job until it ends and pulls its artifact, or only prints what it logged so far.
+pub fn attach(path: &String, id: &str, follow: bool) -> Result<()> {
+    if id.is_empty() {
+        return Err(Error::Argument(string!("Missing a job id, list jobs with `maid --remote jobs`")));
+    }
+
+    let values = parse::merge(path)?;
+
+    // the artifact is placed where the task in this Maidfile pulls to
+    let pulls = match follow {
+        true => list(&values)?
+            .into_iter()
+            .find(|job| job.id == id)
+            .and_then(|job| values.tasks.get(&job.task)?.remote.clone())
+            .map(|remote| remote.pull)
+            .unwrap_or_default(),
+        false => vec![],
+    };
+
+    let (_, websocket, token, host, port) = server::parse::all(values);
+
+    debug!("connecting to {host}:{port}");
+
+    let request = Request::Attach { attach: id.to_string(), follow };
+
+    match drive(&websocket, &token, &request, &[], &pulls)? {
+        Ended::Exited(code) if follow => report(format!("job {id}"), code),
+        Ended::Exited(None) => Err(Error::Remote(format!("Unable to read the output of job {id}"))),
+        _ => Ok(()),
+    }
+}
+
+fn list(values: &Maidfile<Value>) -> Result<Vec<JobSummary>> {
+    let address = server::parse::address(values);
+    let token = server::parse::token(values);
+
+    let response = match Client::new().get(fmtstr!("{address}/api/jobs")).header("Authorization", fmtstr!("Bearer {token}")).send() {
+        Ok(res) => res,
+        Err(err) => return Err(Error::Remote(format!("Unable to connect to the maid server. Is it up? ({err})"))),
+    };
+
+    match response.json::<Vec<JobSummary>>() {
+        Ok(jobs) => Ok(jobs),
+        Err(err) => Err(Error::Remote(format!("Unable to list jobs. Is the token correct? ({err})"))),
+    }
+}
+
+pub fn jobs(path: &String) -> Result<()> {
+    let values = parse::merge(path)?;
+    let jobs = list(&values)?;
+
+    if jobs.is_empty() {
+        println!("{}", "no jobs on the server".white());
+        return Ok(());
+    }
+
+    println!("{}", format!("{:<14}{:<24}{:<12}{:<6}{}", "id", "task", "status", "code", "started").bright_white().bold());
+
+    for job in jobs {
+        let status = format!("{:<12}", job.status.to_string());
+        let status = match job.status {
+            JobStatus::Succeeded => status.bright_green(),
+            JobStatus::Failed => status.bright_red(),
+            JobStatus::Cancelled => status.yellow(),
+            JobStatus::Queued | JobStatus::Running => status.bright_cyan(),
+        };
+
+        let code = job.code.map(|code| code.to_string()).unwrap_or_else(|| string!("-"));
+        println!("{:<14}{:<24}{status}{:<6}{}", job.id, job.task, code, ago(job.created).white());
+    }
+
+    Ok(())
+}
+
+fn ago(time: i64) -> String {
+    let seconds = (protocol::now() - time).max(0) / 1000;
+
+    match seconds {
+        0..=59 => format!("{seconds}s ago"),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn report(task: String, code: Option<i64>) -> Result<()> {
+    match code {
+        Some(0) => println!("\n{} {}", maid::colors::OK, "finished task successfully".bright_green()),
+        Some(code) => println!("\n{} {} {}", maid::colors::FAIL, "remote task exited with status code".bright_red(), format!("{code}").red()),
+        None => {}
     };
 
-    let file_name = match server::file::write_tar(&task.remote.unwrap().push) {
-        Ok(name) => name,
-        Err(err) => error!(%err, "Unable to create archive"),
+    match code {
+        Some(0) => Ok(()),
+        code => Err(Error::RemoteFailed {
+            task,
+            code: code.map(|code| code as i32),
+        }),
+    }
+}
+
+// reconnects when a transfer drops, picking it up where it stopped
+fn drive(websocket: &str, token: &str, request: &Request<Value>, entries: &[CacheEntry], pulls: &[Pull]) -> Result<Ended> {
+    let mut stage = Stage::Connect;
+    let mut attempt = 1;
+
+    loop {
+        let result = open(websocket, token).and_then(|mut socket| {
+            let request = match &stage {
+                Stage::Download(id) => Request::Fetch { fetch: id.clone() },
+                _ => request.clone(),
+            };
+
+            session(&mut socket, &request, entries, pulls, &mut stage)
+        });
+
+        match result {
+            Ok(ended) => return Ok(ended),
+            Err(Error::Remote(reason)) if stage.resumable() && attempt < ATTEMPTS => {
+                attempt += 1;
+                crate::log!(Level::Warning, "{reason}, reconnecting to resume ({attempt}/{ATTEMPTS})");
+                std::thread::sleep(Duration::from_secs(2));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn open(websocket: &str, token: &str) -> Result<WebSocket<MaybeTlsStream<TcpStream>>> {
+    let mut request = websocket.into_client_request().map_err(|err| Error::Remote(format!("Can't connect: {err}")))?;
+    request.headers_mut().insert("Authorization", fmtstr!("Bearer {token}").parse().unwrap());
+
+    let (mut socket, response) = connect_with_config(request, None, 3).map_err(|err| Error::Remote(format!("Can't connect: {err}")))?;
+    debug!("response code: {}", response.status());
+
+    handshake(&mut socket)?;
+
+    Ok(socket)
+}
+
+// the server answers the manifest with the objects it lacks, only those are bundled and sent
+fn push<S: Read + Write>(socket: &mut WebSocket<S>, entries: &[CacheEntry]) -> Result<()> {
+    let manifest = Manifest { entries: entries.to_vec() };
+
+    socket
+        .send(Message::Text(serde_json::to_string(&manifest).unwrap()))
+        .map_err(|err| Error::Remote(format!("Connection lost ({err})")))?;
+
+    let missing = match server::transfer::next(socket)? {
+        Message::Text(text) => match (serde_json::from_str::<Missing>(&text), serde_json::from_str::<Response>(&text)) {
+            (Ok(missing), _) => missing.missing,
+            // the server refused the push, trying again would not change that
+            (Err(_), Ok(Response { message: Some(reason), .. })) => return Err(Error::Status(format!("The maid server refused the push: {reason}"))),
+            (Err(err), _) => return Err(Error::Remote(format!("Expected the missing objects ({err})"))),
+        },
+        _ => return Err(Error::Remote(string!("Expected the missing objects, got binary data"))),
     };
 
+    let files = entries.iter().filter(|entry| entry.kind == EntryKind::File).count();
+
+    if missing.is_empty() {
+        crate::log!(Level::Info, "server already has all {files} files");
+        return Ok(());
+    }
+
+    let wanted: HashSet<&String> = missing.iter().collect();
+    let size: u64 = entries.iter().filter(|entry| entry.hash.as_ref().is_some_and(|hash| wanted.contains(hash))).map(|entry| entry.size).sum();
+    crate::log!(Level::Info, "uploading {} of {files} files ({})", missing.len(), human_bytes(size as f64));
+
+    let bundle = server::file::bundle(entries, &missing).map_err(|err| Error::io("Unable to bundle push files", err))?;
+    let result = server::transfer::send(socket, Path::new(&bundle));
+
+    server::file::remove_tar(&bundle);
+    result
+}
+
+fn session<S: Read + Write>(socket: &mut WebSocket<S>, request: &Request<Value>, entries: &[CacheEntry], pulls: &[Pull], stage: &mut Stage) -> Result<Ended> {
     debug!("sending information");
-    socket.send(Message::Text(serde_json::to_string(&connection_data).unwrap())).unwrap();
+    socket
+        .send(Message::Text(serde_json::to_string(request).unwrap()))
+        .map_err(|err| Error::Remote(format!("Connection lost ({err})")))?;
 
     loop {
-        match socket.read() {
-            Ok(Message::Text(text)) => {
-                if let Ok(Websocket { message, kind, level, .. }) = serde_json::from_str::<Websocket>(&text) {
-                    match kind {
-                        Kind::Done => break,
-                        Kind::Message => crate::log!(level, "{}", message.unwrap()),
-                        Kind::Binary => socket.send(Message::Binary(std::fs::read(&file_name).unwrap())).unwrap(),
-                    }
+        let text = match socket.read() {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) => return Err(Error::Remote(string!("The maid server closed the connection"))),
+            Err(err) => return Err(Error::Remote(format!("Connection lost ({err})"))),
+            _ => continue,
+        };
+
+        if let Ok(frame) = serde_json::from_str::<Transfer>(&text) {
+            let (id, size) = match frame {
+                Transfer::Offer { id, size } => (id, size),
+                _ => return Err(Error::Remote(string!("Unexpected transfer frame from the maid server"))),
+            };
+
+            *stage = Stage::Download(id.clone());
+            let archive = server::transfer::receive(socket, &id, size)?;
+            *stage = Stage::Run;
+
+            let unpacked = server::file::unpack_pulls(&archive.display().to_string(), pulls);
+            server::file::remove_tar(&archive.display().to_string());
+
+            let sizes = unpacked?;
+
+            for (pull, size) in pulls.iter().zip(sizes) {
+                if let Some(size) = size {
+                    println!(
+                        "{} ({})",
+                        format!("pulled '{}' into '{}'", pull.path, pull.to.as_deref().unwrap_or(".")).magenta(),
+                        human_bytes(size as f64).to_string().white()
+                    );
                 }
             }
-            Ok(Message::Binary(archive)) => {
-                let archive_name = match server::file::read_tar(&archive) {
-                    Ok(name) => name,
-                    Err(err) => error!(%err, "Unable to read archive"),
-                };
-
-                if let Err(err) = server::file::unpack_tar(&archive_name) {
-                    error!(%err, "Unable to create archive")
+
+            continue;
+        }
+
+        if let Ok(Response { message, kind, level, code: status, step, .. }) = serde_json::from_str::<Response>(&text) {
+            match kind {
+                Kind::Message => crate::log!(level, "{}", message.unwrap_or_default()),
+                Kind::Binary => {
+                    *stage = Stage::Upload;
+                    push(socket, entries)?;
+                    *stage = Stage::Run;
                 }
+                Kind::Job => {
+                    let id = message.unwrap_or_default();
 
-                server::file::remove_tar(&archive_name);
-            }
-            Err(err) => {
-                crate::log!(Level::Fatal, "{err}");
-                break;
+                    if let Request::Run(data) = request {
+                        if data.info.detach {
+                            close(socket, "detached from job");
+                            return Ok(Ended::Detached(id));
+                        }
+                    }
+
+                    crate::log!(Level::Info, "running as job {id}");
+                }
+                Kind::Step => match step {
+                    Some(Step { task, index, total, command, phase: Phase::Start, .. }) => crate::log!(level, "[{task} {index}/{total}] {}", command.white()),
+                    Some(Step { task, index, total, duration, phase: Phase::End, .. }) => {
+                        let took = format!("{:.2?}", Duration::from_millis(duration.unwrap_or_default())).yellow();
+                        match status {
+                            Some(0) => crate::log!(level, "[{task} {index}/{total}] done in {took}"),
+                            Some(code) => crate::log!(level, "[{task} {index}/{total}] exited with {code} after {took}"),
+                            None => crate::log!(level, "[{task} {index}/{total}] was killed after {took}"),
+                        }
+                    }
+                    None => {}
+                },
+                Kind::Done => {
+                    let reason = match status {
+                        Some(0) => "finished task successfully",
+                        Some(_) => "task failed",
+                        None => "task did not finish",
+                    };
+
+                    close(socket, reason);
+                    return Ok(Ended::Exited(status));
+                }
             }
-            _ => (),
-        };
+        }
     }
+}
 
-    server::file::remove_tar(&file_name);
-    // run.rs:96 implement that later
-    println!("\n{} {}", maid::colors::OK, "finished task successfully".bright_green());
-    println!("{}", "removed temporary archive".bright_magenta());
-
+fn close<S: Read + Write>(socket: &mut WebSocket<S>, reason: &'static str) {
     if let Err(err) = socket.close(Some(CloseFrame {
         code: Normal,
-        // run.rs:96 implement that later
-        reason: std::borrow::Cow::Borrowed("finished task successfully"),
+        reason: std::borrow::Cow::Borrowed(reason),
     })) {
-        error!(%err, "Unable to close socket")
+        debug!(%err, "Unable to close socket");
     };
 }
@@ -1,64 +1,148 @@
+use crate::task;
+
 use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use global_placeholders::global;
 use macros_rs::fs::folder_exists;
-use maid::log::prelude::*;
-use std::{fs::write, fs::File, path::PathBuf};
-use tar::{Archive, Builder};
-use uuid::Uuid;
+use maid::{
+    archive::{self, Limits},
+    log::prelude::*,
+    models::{
+        client::{CacheEntry, EntryKind},
+        shared::Pull,
+    },
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
 
-fn append_to_tar(builder: &mut Builder<GzEncoder<File>>, path: &String) -> Result<(), std::io::Error> {
-    let pathbuf = PathBuf::from(path);
+use tar::{Builder, Header};
+use uuid::Uuid;
 
-    if pathbuf.is_file() {
-        builder.append_path(&pathbuf)?;
-    } else if pathbuf.is_dir() {
-        builder.append_dir_all(&pathbuf, &pathbuf)?;
+pub fn remove_tar(file: &String) {
+    if std::fs::remove_file(file).is_err() {
+        warn!("Unable to remove temporary archive. does it exist?");
     }
-    Ok(())
 }
 
-pub fn remove_tar(file: &String) {
-    if let Err(_) = std::fs::remove_file(file) {
-        error!("Unable to remove temporary archive. does it exist?");
-    }
+/// Unpacks a pulled artifact, the matches of every pull into its own destination and nowhere else.
+/// Returns how many bytes each pull brought back, none for pulls that matched nothing.
+pub fn unpack_pulls(path: &String, pulls: &[Pull]) -> maid::Result<Vec<Option<u64>>> {
+    let roots: Vec<PathBuf> = pulls.iter().map(|pull| PathBuf::from(pull.to.as_deref().unwrap_or("."))).collect();
+
+    // the server files the matches of each pull under its index
+    let route = |name: &Path| {
+        let mut components = name.components();
+
+        match components.next().and_then(|item| item.as_os_str().to_str()?.parse::<usize>().ok()).filter(|index| *index < pulls.len()) {
+            Some(index) => Ok((index, components.as_path().to_path_buf())),
+            None => Err(String::from("no pull of this task declares it")),
+        }
+    };
+
+    let open = || Ok(GzDecoder::new(File::open(path)?));
+    Ok(archive::extract(open, &roots, &Limits::default(), &route)?)
 }
 
-pub fn read_tar(archive: &Vec<u8>) -> Result<String, std::io::Error> {
-    if !folder_exists!(&global!("maid.temp_dir")) {
-        std::fs::create_dir_all(global!("maid.temp_dir")).unwrap();
-        debug!("created maid temp dir");
-    }
+fn walk(path: &Path, entries: &mut BTreeMap<String, CacheEntry>) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    let name = path.to_string_lossy().replace('\\', "/").trim_start_matches("./").to_string();
 
-    let file_name = format!("{}/{}.tgz", global!("maid.temp_dir"), Uuid::new_v4());
-    write(&file_name, archive)?;
+    if metadata.file_type().is_symlink() {
+        entries.insert(
+            name.clone(),
+            CacheEntry {
+                path: name,
+                kind: EntryKind::Symlink,
+                mode: 0,
+                size: 0,
+                hash: None,
+                link: Some(fs::read_link(path)?.to_string_lossy().into_owned()),
+            },
+        );
+    } else if metadata.is_dir() {
+        if !name.is_empty() && name != "." {
+            entries.insert(
+                name.clone(),
+                CacheEntry {
+                    path: name,
+                    kind: EntryKind::Directory,
+                    mode: task::store::mode(&metadata),
+                    size: 0,
+                    hash: None,
+                    link: None,
+                },
+            );
+        }
 
-    Ok(file_name)
+        for child in fs::read_dir(path)? {
+            walk(&child?.path(), entries)?;
+        }
+    } else {
+        entries.insert(
+            name.clone(),
+            CacheEntry {
+                path: name,
+                kind: EntryKind::File,
+                mode: task::store::mode(&metadata),
+                size: metadata.len(),
+                hash: Some(task::cache::create_hash(path)),
+                link: None,
+            },
+        );
+    }
+
+    Ok(())
 }
 
-pub fn unpack_tar(path: &String) -> std::io::Result<()> {
-    let archive = File::open(&path)?;
-    let tar = GzDecoder::new(archive);
-    let mut archive = Archive::new(tar);
+/// Lists everything in `Remote::push` with the same content hashes the task cache uses.
+pub fn manifest(files: &Vec<String>) -> io::Result<Vec<CacheEntry>> {
+    let mut entries = BTreeMap::new();
 
-    archive.unpack(".")
+    for path in files {
+        match Path::new(path).exists() {
+            true => walk(Path::new(path), &mut entries)?,
+            false => warn!("Push path '{path}' does not exist, skipping"),
+        }
+    }
+
+    Ok(entries.into_values().collect())
 }
 
-pub fn write_tar(files: &Vec<String>) -> Result<String, std::io::Error> {
+// objects are named by their hash and written in a fixed order with fixed headers,
+// so rebuilding the bundle after a dropped connection lets the transfer resume
+pub fn bundle(entries: &[CacheEntry], missing: &[String]) -> io::Result<String> {
     if !folder_exists!(&global!("maid.temp_dir")) {
-        std::fs::create_dir_all(global!("maid.temp_dir")).unwrap();
+        std::fs::create_dir_all(global!("maid.temp_dir"))?;
         debug!("created maid temp dir");
     }
 
     let file_name = format!("{}/{}.tgz", global!("maid.temp_dir"), Uuid::new_v4());
-    let archive = File::create(&file_name)?;
-    let enc = GzEncoder::new(archive, Compression::default());
+    let enc = GzEncoder::new(File::create(&file_name)?, Compression::default());
     let mut tar = Builder::new(enc);
 
-    debug!("compressing to {}", &file_name);
-    for path in files {
-        append_to_tar(&mut tar, path)?;
-        debug!("{} {:?}", maid::colors::ADD, path);
+    let sources: BTreeMap<&str, &str> = entries.iter().filter_map(|entry| Some((entry.hash.as_deref()?, entry.path.as_str()))).collect();
+    let wanted: BTreeSet<&String> = missing.iter().collect();
+
+    for hash in wanted {
+        let source = match sources.get(hash.as_str()) {
+            Some(source) => source,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("server asked for unknown object {hash}"))),
+        };
+
+        let mut header = Header::new_gnu();
+        header.set_size(fs::metadata(source)?.len());
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_cksum();
+
+        tar.append_data(&mut header, hash, File::open(source)?)?;
+        debug!("{} {source}", maid::colors::ADD);
     }
 
+    tar.into_inner()?.finish()?;
+
     Ok(file_name)
 }
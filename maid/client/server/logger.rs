@@ -2,22 +2,22 @@
 macro_rules! log {
     ($level:expr, $($arg:tt)*) => {{
         lazy_static::lazy_static! {
-            static ref LEVEL_COLORS: std::collections::HashMap<Level, (&'static str, colored::Color)> = {
+            static ref LEVEL_COLORS: std::collections::HashMap<maid::protocol::Level, (&'static str, colored::Color)> = {
                 let mut map = std::collections::HashMap::new();
-                map.insert(Level::Fatal, ("FATAL", colored::Color::BrightRed));
-                map.insert(Level::Docker, ("DOCKER", colored::Color::BrightYellow));
-                map.insert(Level::Info, ("INFO", colored::Color::Cyan));
-                map.insert(Level::Build, ("BUILD", colored::Color::BrightGreen));
-                map.insert(Level::Success, ("SUCCESS", colored::Color::Green));
-                map.insert(Level::Debug, ("DEBUG", colored::Color::Magenta));
-                map.insert(Level::Notice, ("NOTICE", colored::Color::BrightBlue));
-                map.insert(Level::Warning, ("WARN", colored::Color::Yellow));
-                map.insert(Level::Error, ("ERROR", colored::Color::Red));
+                map.insert(maid::protocol::Level::Fatal, ("FATAL", colored::Color::BrightRed));
+                map.insert(maid::protocol::Level::Docker, ("DOCKER", colored::Color::BrightYellow));
+                map.insert(maid::protocol::Level::Info, ("INFO", colored::Color::Cyan));
+                map.insert(maid::protocol::Level::Build, ("BUILD", colored::Color::BrightGreen));
+                map.insert(maid::protocol::Level::Success, ("SUCCESS", colored::Color::Green));
+                map.insert(maid::protocol::Level::Debug, ("DEBUG", colored::Color::Magenta));
+                map.insert(maid::protocol::Level::Notice, ("NOTICE", colored::Color::BrightBlue));
+                map.insert(maid::protocol::Level::Warning, ("WARN", colored::Color::Yellow));
+                map.insert(maid::protocol::Level::Error, ("ERROR", colored::Color::Red));
                 return map;
             };
         }
 
-        if $level == Level::None {
+        if $level == maid::protocol::Level::None {
             print!("{}", format_args!($($arg)*).to_string());
         } else {
             match LEVEL_COLORS.get(&$level) {
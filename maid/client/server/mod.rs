@@ -1,5 +1,7 @@
 pub(crate) mod api;
+pub(crate) mod cache;
 pub(crate) mod cli;
 pub(crate) mod file;
 pub(crate) mod logger;
 pub(crate) mod parse;
+pub(crate) mod transfer;
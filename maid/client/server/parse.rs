@@ -41,7 +41,7 @@ pub fn host(values: &Maidfile<Value>) -> String {
 pub fn port(values: &Maidfile<Value>) -> i64 {
     match &values.project {
         Some(project) => match &project.server {
-            Some(server) => server.address.port.clone(),
+            Some(server) => server.address.port,
             None => 0,
         },
         None => 0,
@@ -0,0 +1,137 @@
+use global_placeholders::global;
+use human_bytes::human_bytes;
+use indicatif::{ProgressBar, ProgressStyle};
+use maid::{
+    log::prelude::*,
+    protocol::{Transfer, CHUNK_SIZE},
+    transfer::{checksum, chunks, Chunk, Partial},
+    Error, Result,
+};
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use tungstenite::{Message, WebSocket};
+
+// resend rounds before a transfer that keeps failing its checksums is given up
+const ROUNDS: usize = 5;
+
+pub(crate) fn next<S: Read + Write>(socket: &mut WebSocket<S>) -> Result<Message> {
+    loop {
+        match socket.read() {
+            Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => continue,
+            Ok(Message::Close(_)) => return Err(Error::Remote("Connection closed during transfer".to_string())),
+            Ok(message) => return Ok(message),
+            Err(err) => return Err(Error::Remote(format!("Connection lost during transfer ({err})"))),
+        }
+    }
+}
+
+fn write<S: Read + Write>(socket: &mut WebSocket<S>, message: Message) -> Result<()> {
+    socket.send(message).map_err(|err| Error::Remote(format!("Connection lost during transfer ({err})")))
+}
+
+fn control<S: Read + Write>(socket: &mut WebSocket<S>) -> Result<Transfer> {
+    match next(socket)? {
+        Message::Text(text) => serde_json::from_str(&text).map_err(|err| Error::Remote(format!("Expected a transfer frame ({err})"))),
+        _ => Err(Error::Remote("Expected a transfer frame, got binary data".to_string())),
+    }
+}
+
+fn progress(label: &'static str, size: u64) -> ProgressBar {
+    let bar = ProgressBar::new(size);
+    let style = ProgressStyle::with_template("{msg} [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})").unwrap();
+
+    bar.set_style(style.progress_chars("=> "));
+    bar.set_message(label);
+    bar
+}
+
+fn position(index: u64, size: u64) -> u64 { (index * CHUNK_SIZE as u64).min(size) }
+
+pub(crate) fn send<S: Read + Write>(socket: &mut WebSocket<S>, path: &Path) -> Result<()> {
+    let id = checksum(path).map_err(|err| Error::io("Unable to hash archive", err))?;
+    let mut file = File::open(path).map_err(|err| Error::io("Unable to open archive", err))?;
+    let size = file.metadata().map_err(|err| Error::io("Unable to read archive", err))?.len();
+
+    write(socket, Message::Text(Transfer::Offer { id: id.clone(), size }.to_json()))?;
+
+    let bar = progress("pushing", size);
+
+    for _ in 0..=ROUNDS {
+        match control(socket)? {
+            Transfer::Complete { .. } => {
+                bar.finish();
+                return Ok(());
+            }
+            Transfer::Resume { from, .. } => {
+                if from > 0 {
+                    bar.println(format!("resuming push at {}", human_bytes(position(from, size) as f64)));
+                }
+
+                bar.set_position(position(from, size));
+
+                for index in from..chunks(size) {
+                    let frame = Chunk::read(&mut file, index).map_err(|err| Error::io("Unable to read archive", err))?;
+                    write(socket, Message::Binary(frame))?;
+                    bar.set_position(position(index + 1, size));
+                }
+            }
+            Transfer::Offer { .. } => return Err(Error::Remote("Unexpected transfer offer while pushing".to_string())),
+        }
+    }
+
+    Err(Error::Remote(format!("Push {id} was not accepted after {ROUNDS} rounds")))
+}
+
+pub(crate) fn receive<S: Read + Write>(socket: &mut WebSocket<S>, id: &str, size: u64) -> Result<PathBuf> {
+    let dir = PathBuf::from(global!("maid.transfers_dir"));
+    let mut partial = Partial::open(&dir, id, size).map_err(|err| Error::io("Unable to stage artifact", err))?;
+    let mut rounds = 0;
+
+    let bar = progress("pulling", size);
+
+    if partial.next() > 0 {
+        bar.println(format!("resuming pull at {}", human_bytes(partial.received() as f64)));
+    }
+
+    loop {
+        if partial.done() {
+            match partial.finish().map_err(|err| Error::io("Unable to verify artifact", err))? {
+                Some(path) => {
+                    write(socket, Message::Text(Transfer::Complete { id: id.to_string() }.to_json()))?;
+                    bar.finish();
+                    return Ok(path);
+                }
+                None => {
+                    warn!("Artifact {id} does not match its checksum, starting over");
+                    partial = Partial::open(&dir, id, size).map_err(|err| Error::io("Unable to stage artifact", err))?;
+                }
+            }
+        }
+
+        rounds += 1;
+        if rounds > ROUNDS {
+            return Err(Error::Remote(format!("Pull {id} is still incomplete after {ROUNDS} rounds")));
+        }
+
+        let from = partial.next();
+        bar.set_position(partial.received());
+        write(socket, Message::Text(Transfer::Resume { id: id.to_string(), from }.to_json()))?;
+
+        for _ in from..chunks(size) {
+            let chunk = match next(socket)? {
+                Message::Binary(frame) => Chunk::decode(&frame).ok_or_else(|| Error::Remote(format!("Malformed chunk in pull {id}")))?,
+                _ => return Err(Error::Remote(format!("Expected a chunk of pull {id}"))),
+            };
+
+            match partial.write(&chunk).map_err(|err| Error::io("Unable to write artifact", err))? {
+                true => bar.set_position(partial.received()),
+                false => debug!("dropped chunk {} of pull {id}", chunk.index),
+            }
+        }
+    }
+}
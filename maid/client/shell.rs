@@ -1,5 +1,6 @@
+use maid::models::shared::Shell;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::mem;
+use std::{mem, path::Path};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) enum ParseError {
@@ -31,6 +32,34 @@ enum State {
     DoubleQuotedBackslash,
 }
 
+// the interpreter a rendered script line is passed to, none runs the line as argv
+pub(crate) fn interpreter(task: Option<&Shell>, project: Option<&Shell>) -> Option<Vec<String>> {
+    match task.or(project) {
+        None | Some(Shell::Enabled(true)) => Some(default_shell()),
+        Some(Shell::Enabled(false)) => None,
+        Some(Shell::Program(program)) => Some(vec![program.to_owned(), command_flag(program).to_string()]),
+        Some(Shell::Command(command)) if command.is_empty() => Some(default_shell()),
+        Some(Shell::Command(command)) => Some(command.to_owned()),
+    }
+}
+
+fn default_shell() -> Vec<String> {
+    match cfg!(windows) {
+        true => vec!["cmd".to_string(), "/C".to_string()],
+        false => vec!["sh".to_string(), "-c".to_string()],
+    }
+}
+
+fn command_flag(program: &str) -> &'static str {
+    let name = Path::new(program).file_stem().and_then(|name| name.to_str()).unwrap_or(program);
+
+    match name.to_lowercase().as_str() {
+        "cmd" => "/C",
+        "pwsh" | "powershell" => "-Command",
+        _ => "-c",
+    }
+}
+
 pub(crate) trait IntoArgs {
     fn try_into_args(&self) -> Result<Vec<String>, ParseError>;
 }
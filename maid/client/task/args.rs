@@ -0,0 +1,176 @@
+use maid::{
+    models::shared::{Argument, ArgumentKind, Tasks},
+    Error, Result,
+};
+
+use std::collections::BTreeMap;
+
+fn signature(name: &str, argument: &Argument) -> String {
+    let value = match argument.kind {
+        ArgumentKind::String => "string".to_string(),
+        ArgumentKind::Int => "int".to_string(),
+        ArgumentKind::Bool => "bool".to_string(),
+        ArgumentKind::Enum => argument.values.join("|"),
+    };
+
+    format!("--{name}=<{value}>")
+}
+
+pub(crate) fn usage(name: &str, argument: &Argument) -> String {
+    match &argument.default {
+        Some(default) => format!("[{}, default {default}]", signature(name, argument)),
+        None => signature(name, argument),
+    }
+}
+
+fn validate(task: &str, name: &str, argument: &Argument, value: &str) -> Result<()> {
+    let valid = match argument.kind {
+        ArgumentKind::String => true,
+        ArgumentKind::Int => value.parse::<i64>().is_ok(),
+        ArgumentKind::Bool => value == "true" || value == "false",
+        ArgumentKind::Enum if argument.values.is_empty() => return Err(Error::Argument(format!("Argument '--{name}' of task '{task}' is an enum without values."))),
+        ArgumentKind::Enum => argument.values.iter().any(|item| item == value),
+    };
+
+    match valid {
+        true => Ok(()),
+        false => Err(Error::Argument(format!("Invalid value '{value}' for argument {} of task '{task}'", signature(name, argument)))),
+    }
+}
+
+// validates named arguments (--name=value) against the task declaration and fills in defaults,
+// named arguments the task does not declare are an error unless they belong to another task
+pub(crate) fn resolve<T>(task: &str, values: &Tasks<T>, args: &[String], strict: bool) -> Result<Vec<String>> {
+    let declared = values.args.clone().unwrap_or_default();
+    let mut named: BTreeMap<String, String> = BTreeMap::new();
+    let mut resolved: Vec<String> = vec![];
+
+    for arg in args {
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) if !flag.is_empty() => flag,
+            _ => {
+                resolved.push(arg.to_owned());
+                continue;
+            }
+        };
+
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (flag, None),
+        };
+
+        let value = match (declared.get(name), value) {
+            (_, Some(value)) => value,
+            (Some(argument), None) if argument.kind == ArgumentKind::Bool => "true",
+            (Some(argument), None) => return Err(Error::Argument(format!("Missing value for argument {} of task '{task}'", signature(name, argument)))),
+            (None, None) => "true",
+        };
+
+        if strict && !declared.contains_key(name) {
+            return Err(Error::Argument(format!("Unknown argument '--{name}' for task '{task}'")));
+        }
+
+        named.insert(name.to_string(), value.to_string());
+    }
+
+    for (name, argument) in &declared {
+        if !named.contains_key(name) {
+            match &argument.default {
+                Some(default) => named.insert(name.to_owned(), default.to_owned()),
+                None => return Err(Error::Argument(format!("Missing required argument {} for task '{task}'", signature(name, argument)))),
+            };
+        }
+
+        validate(task, name, argument, &named[name])?;
+    }
+
+    resolved.extend(named.into_iter().map(|(name, value)| format!("--{name}={value}")));
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(args: &str) -> Tasks<toml::Value> { toml::from_str(&format!("script = \"echo\"\n{args}")).unwrap() }
+
+    fn resolved(values: &Tasks<toml::Value>, args: &[&str], strict: bool) -> Result<Vec<String>> {
+        resolve("deploy", values, &args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>(), strict)
+    }
+
+    fn refused(values: &Tasks<toml::Value>, args: &[&str], strict: bool) -> String {
+        match resolved(values, args, strict) {
+            Ok(args) => panic!("expected an error, got {args:?}"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn positional_arguments_pass_through_in_order() {
+        let values = task("");
+        assert_eq!(resolved(&values, &["deploy", "one", "two", "--"], true).unwrap(), ["deploy", "one", "two", "--"]);
+    }
+
+    #[test]
+    fn defaults_fill_in_missing_arguments() {
+        let values = task("[args.env]\ndefault = \"dev\"\n[args.region]\ndefault = \"eu\"");
+        assert_eq!(resolved(&values, &["deploy", "--env=prod"], true).unwrap(), ["deploy", "--env=prod", "--region=eu"]);
+    }
+
+    #[test]
+    fn required_arguments_must_be_given() {
+        let values = task("[args.env]\ntype = \"string\"");
+
+        assert!(refused(&values, &["deploy"], true).contains("Missing required argument --env=<string>"));
+        assert!(refused(&values, &["deploy", "--env"], true).contains("Missing value for argument --env=<string>"));
+        assert_eq!(resolved(&values, &["deploy", "--env="], true).unwrap(), ["deploy", "--env="]);
+    }
+
+    #[test]
+    fn bool_arguments_may_leave_out_the_value() {
+        let values = task("[args.dry]\ntype = \"bool\"\ndefault = false");
+
+        assert_eq!(resolved(&values, &["deploy", "--dry"], true).unwrap(), ["deploy", "--dry=true"]);
+        assert_eq!(resolved(&values, &["deploy"], true).unwrap(), ["deploy", "--dry=false"]);
+        assert!(refused(&values, &["deploy", "--dry=yes"], true).contains("Invalid value 'yes'"));
+    }
+
+    #[test]
+    fn int_arguments_must_be_numbers() {
+        let values = task("[args.replicas]\ntype = \"int\"\ndefault = 1");
+
+        assert_eq!(resolved(&values, &["deploy", "--replicas=-3"], true).unwrap(), ["deploy", "--replicas=-3"]);
+        assert!(refused(&values, &["deploy", "--replicas=three"], true).contains("--replicas=<int>"));
+    }
+
+    #[test]
+    fn enum_arguments_take_one_of_their_values() {
+        let values = task("[args.env]\ntype = \"enum\"\nvalues = [\"dev\", \"prod\"]");
+
+        assert_eq!(resolved(&values, &["deploy", "--env=prod"], true).unwrap(), ["deploy", "--env=prod"]);
+        assert!(refused(&values, &["deploy", "--env=staging"], true).contains("--env=<dev|prod>"));
+
+        let empty = task("[args.env]\ntype = \"enum\"\ndefault = \"dev\"");
+        assert!(refused(&empty, &["deploy"], true).contains("is an enum without values"));
+    }
+
+    #[test]
+    fn unknown_arguments_only_fail_when_strict() {
+        let values = task("");
+
+        assert!(refused(&values, &["deploy", "--env=prod"], true).contains("Unknown argument '--env'"));
+
+        // a dependency sees the arguments meant for the task that was asked for
+        assert_eq!(resolved(&values, &["deploy", "--env=prod", "--verbose"], false).unwrap(), ["deploy", "--env=prod", "--verbose=true"]);
+    }
+
+    #[test]
+    fn usage_shows_defaults() {
+        let values = task("[args.env]\ntype = \"enum\"\nvalues = [\"dev\", \"prod\"]\ndefault = \"dev\"\n[args.tag]\ntype = \"string\"");
+        let args = values.args.unwrap();
+
+        assert_eq!(usage("env", &args["env"]), "[--env=<dev|prod>, default dev]");
+        assert_eq!(usage("tag", &args["tag"]), "--tag=<string>");
+    }
+}
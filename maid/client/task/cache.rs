@@ -1,52 +1,315 @@
-use maid::log::prelude::*;
+use maid::{
+    log::prelude::*,
+    models::{client::CacheKey, shared::Cache},
+};
+
+use glob::Pattern;
+use macros_rs::exp::then;
 use merkle_hash::{Algorithm, MerkleTree};
-use std::path::Path;
+use std::{borrow::Cow, collections::BTreeMap, collections::BTreeSet, collections::HashMap, fs, path::Path, path::PathBuf};
+use text_placeholder::Template;
 
 const DEFAULT_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
 
-fn bytes_to_hex(bytes: impl AsRef<[u8]>) -> &'static str {
+fn bytes_to_hex(bytes: impl AsRef<[u8]>) -> String {
     const TABLE: &[u8; 16] = b"0123456789abcdef";
-    static mut HEX_BUFFER: [u8; 1024] = [0; 1024];
-
-    let bytes = bytes.as_ref();
-    let len = bytes.len();
-
-    // Safety: we ensure exclusive access and stay within bounds
-    unsafe {
-        let buf = &mut HEX_BUFFER[..(len * 2)];
+    let mut hex = String::with_capacity(bytes.as_ref().len() * 2);
 
-        for (i, &byte) in bytes.iter().enumerate() {
-            let idx = i * 2;
-            buf[idx] = TABLE[(byte >> 4) as usize];
-            buf[idx + 1] = TABLE[(byte & 0xf) as usize];
-        }
-
-        std::str::from_utf8_unchecked(&buf[..(len * 2)])
+    for &byte in bytes.as_ref() {
+        hex.push(TABLE[(byte >> 4) as usize] as char);
+        hex.push(TABLE[(byte & 0xf) as usize] as char);
     }
+
+    hex
 }
 
-pub(crate) fn create_hash<'hash>(path: impl AsRef<Path>) -> &'hash str {
+pub(crate) fn create_hash(path: impl AsRef<Path>) -> String {
     let path = path.as_ref();
 
     if !path.exists() {
         warn!("Path does not exist: {}", path.display());
-        return DEFAULT_HASH;
+        return DEFAULT_HASH.to_string();
     }
 
     let path = match path.to_str() {
         Some(path) => path,
-        None => return DEFAULT_HASH,
+        None => return DEFAULT_HASH.to_string(),
     };
 
     match MerkleTree::builder(path).algorithm(Algorithm::Blake3).hash_names(false).build() {
         Ok(tree) => {
             let hash = bytes_to_hex(tree.root.item.hash);
-            debug!(path, "Successfully created tree hash");
-            return hash;
+            trace!(path, "Successfully created tree hash");
+            hash
         }
         Err(err) => {
             warn!(%err, path, "Failed to create tree hash");
-            return DEFAULT_HASH;
+            DEFAULT_HASH.to_string()
         }
-    };
+    }
+}
+
+fn excluded(path: &Path, exclude: &[Pattern]) -> bool { exclude.iter().any(|pattern| path.ancestors().any(|item| pattern.matches_path(item))) }
+
+// inputs are named relative to the working dir, so the key does not depend on where the project is
+fn collect(dir: &Path, relative: &Path, exclude: &[Pattern], files: &mut BTreeSet<PathBuf>) {
+    let path = dir.join(relative);
+
+    if excluded(relative, exclude) {
+        return;
+    }
+
+    if path.is_dir() {
+        match fs::read_dir(&path) {
+            Ok(entries) => entries.flatten().for_each(|entry| collect(dir, &relative.join(entry.file_name()), exclude, files)),
+            Err(err) => warn!(%err, "Cannot read cache input {}", path.display()),
+        }
+    } else if path.is_file() {
+        files.insert(relative.to_path_buf());
+    }
+}
+
+pub(crate) fn inputs(dir: &Path, cache: &Cache) -> Vec<PathBuf> {
+    let mut files = BTreeSet::new();
+    let exclude: Vec<Pattern> = cache
+        .exclude
+        .iter()
+        .filter_map(|item| match Pattern::new(item.trim_end_matches('/')) {
+            Ok(pattern) => Some(pattern),
+            Err(err) => {
+                warn!(%err, "Invalid cache exclude pattern '{item}'");
+                None
+            }
+        })
+        .collect();
+
+    let root = Pattern::escape(&dir.to_string_lossy());
+
+    for item in &cache.path {
+        match glob::glob(&format!("{root}/{item}")) {
+            Ok(paths) => {
+                let before = files.len();
+                paths.flatten().filter_map(|path| path.strip_prefix(dir).map(Path::to_path_buf).ok()).for_each(|path| collect(dir, &path, &exclude, &mut files));
+                then!(files.len() == before, warn!("Cache input '{item}' did not match any files"));
+            }
+            Err(err) => warn!(%err, "Invalid cache input pattern '{item}'"),
+        }
+    }
+
+    files.into_iter().collect()
+}
+
+fn hash_inputs(files: &BTreeMap<String, String>) -> String {
+    let mut hasher = blake3::Hasher::new();
+
+    for (file, hash) in files {
+        hasher.update(file.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(hash.as_bytes());
+        hasher.update(b"\n");
+    }
+
+    hasher.finalize().to_hex().to_string()
+}
+
+// placeholders the script fills in, the rest of the table cannot change what it does
+fn referenced(script: &[String]) -> BTreeSet<String> {
+    let mut used = BTreeSet::new();
+
+    for line in script {
+        let _ = Template::new_with_placeholder(line, "%{", "}").fill_with_function(|key| {
+            used.insert(key.to_string());
+            Some(Cow::Borrowed(""))
+        });
+    }
+
+    used
+}
+
+// the same lines mean something else under another interpreter, so it is hashed along with them
+pub(crate) fn create_key(dir: &Path, cache: &Cache, shell: Option<&[String]>, script: &[String], table: &HashMap<&str, &str>) -> CacheKey {
+    let mut rendered: Vec<String> = vec![shell.map_or(String::new(), |shell| shell.join(" "))];
+    rendered.extend(script.iter().map(|line| Template::new_with_placeholder(line, "%{", "}").fill_with_hashmap(table)));
+    let files: BTreeMap<String, String> = inputs(dir, cache).iter().map(|file| (file.to_string_lossy().replace('\\', "/"), create_hash(dir.join(file)))).collect();
+    let used = referenced(script);
+
+    debug!("Hashing {} cache inputs", files.len());
+
+    CacheKey {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        inputs: hash_inputs(&files),
+        script: blake3::hash(rendered.join("\n").as_bytes()).to_hex().to_string(),
+        // env is handed to every command, anything else only counts when the script uses it
+        table: table
+            .iter()
+            .filter(|(key, _)| key.starts_with("env.") || used.contains(**key))
+            .map(|(key, value)| (key.to_string(), blake3::hash(value.as_bytes()).to_hex().to_string()))
+            .collect(),
+        files,
+    }
+}
+
+pub(crate) fn digest(key: &CacheKey) -> String {
+    let mut hasher = blake3::Hasher::new();
+
+    hasher.update(key.version.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(key.inputs.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(key.script.as_bytes());
+    hasher.update(b"\n");
+
+    for (name, value) in &key.table {
+        hasher.update(name.as_bytes());
+        hasher.update(b"=");
+        hasher.update(value.as_bytes());
+        hasher.update(b"\n");
+    }
+
+    hasher.finalize().to_hex().to_string()
+}
+
+pub(crate) fn changes(previous: &CacheKey, current: &CacheKey) -> Vec<(&'static str, Vec<String>)> {
+    let mut changed = vec![];
+
+    if previous.version != current.version {
+        changed.push(("version", vec![format!("{} -> {}", previous.version, current.version)]));
+    }
+
+    if previous.inputs != current.inputs {
+        let files: BTreeSet<&String> = previous.files.keys().chain(current.files.keys()).collect();
+        let files = files.into_iter().filter(|file| previous.files.get(*file) != current.files.get(*file)).map(|file| file.to_string()).collect();
+
+        changed.push(("inputs", files));
+    }
+
+    if previous.script != current.script {
+        changed.push(("script", vec![]));
+    }
+
+    let keys: BTreeSet<&String> = previous.table.keys().chain(current.table.keys()).collect();
+    let table: Vec<String> = keys.into_iter().filter(|key| previous.table.get(*key) != current.table.get(*key)).map(|key| key.to_string()).collect();
+
+    if !table.is_empty() {
+        changed.push(("table", table));
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("maid-cache-{}", uuid::Uuid::new_v4().simple()));
+            fs::create_dir_all(dir.join("src/generated")).unwrap();
+            fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+            fs::write(dir.join("src/lib.rs"), "pub fn lib() {}").unwrap();
+            fs::write(dir.join("src/generated/schema.rs"), "// generated").unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) { let _ = fs::remove_dir_all(&self.0); }
+    }
+
+    fn cache() -> Cache {
+        Cache {
+            path: vec!["src".to_string()],
+            exclude: vec!["src/generated".to_string()],
+            target: vec!["out".to_string()],
+        }
+    }
+
+    fn script() -> Vec<String> { vec!["cargo build --profile %{arg.profile}".to_string(), "cp target/app %{env.OUT}".to_string()] }
+
+    fn table<'a>(extra: &[(&'a str, &'a str)]) -> HashMap<&'a str, &'a str> {
+        let mut table = HashMap::from([("env.OUT", "dist"), ("env.UNUSED", "1"), ("arg.profile", "release"), ("arg.verbose", "true"), ("dir.current", "/home/me/app")]);
+        table.extend(extra.iter().copied());
+        table
+    }
+
+    fn key(scratch: &Scratch, shell: Option<&[String]>, script: &[String], table: &HashMap<&str, &str>) -> CacheKey { create_key(&scratch.0, &cache(), shell, script, table) }
+
+    #[test]
+    fn referenced_lists_every_placeholder() {
+        let used = referenced(&script());
+        assert_eq!(used.into_iter().collect::<Vec<_>>(), ["arg.profile", "env.OUT"]);
+    }
+
+    #[test]
+    fn inputs_skip_excluded_paths() {
+        let scratch = Scratch::new();
+        assert_eq!(inputs(&scratch.0, &cache()), [PathBuf::from("src/lib.rs"), PathBuf::from("src/main.rs")]);
+    }
+
+    #[test]
+    fn same_inputs_give_the_same_digest() {
+        let scratch = Scratch::new();
+        let first = key(&scratch, None, &script(), &table(&[]));
+
+        // the project directory and unreferenced arguments are not part of the key
+        let moved = key(&scratch, None, &script(), &table(&[("dir.current", "/elsewhere"), ("arg.verbose", "false")]));
+
+        assert_eq!(digest(&first), digest(&moved));
+        assert!(changes(&first, &moved).is_empty());
+    }
+
+    #[test]
+    fn changes_to_the_script_env_args_or_shell_change_the_digest() {
+        let scratch = Scratch::new();
+        let base = key(&scratch, None, &script(), &table(&[]));
+        let shell = vec!["bash".to_string(), "-c".to_string()];
+
+        let mut edited = script();
+        edited[1] = "cp target/app %{env.OUT}/bin".to_string();
+
+        // values the script uses change the rendered lines as well as the table
+        let cases = [
+            (key(&scratch, None, &edited, &table(&[])), vec![("script", vec![])]),
+            (key(&scratch, Some(&shell), &script(), &table(&[])), vec![("script", vec![])]),
+            (key(&scratch, None, &script(), &table(&[("arg.profile", "debug")])), vec![("script", vec![]), ("table", vec!["arg.profile"])]),
+            (key(&scratch, None, &script(), &table(&[("env.OUT", "build")])), vec![("script", vec![]), ("table", vec!["env.OUT"])]),
+            // the env reaches every command, even when the script never names it
+            (key(&scratch, None, &script(), &table(&[("env.UNUSED", "2")])), vec![("table", vec!["env.UNUSED"])]),
+        ];
+
+        for (changed, expected) in cases {
+            let expected: Vec<(&str, Vec<String>)> = expected.into_iter().map(|(part, details)| (part, details.into_iter().map(str::to_string).collect())).collect();
+
+            assert_ne!(digest(&base), digest(&changed), "{expected:?} should change the digest");
+            assert_eq!(changes(&base, &changed), expected);
+        }
+    }
+
+    #[test]
+    fn a_new_maid_version_changes_the_digest() {
+        let scratch = Scratch::new();
+        let base = key(&scratch, None, &script(), &table(&[]));
+        let older = CacheKey { version: "0.0.1".to_string(), ..base.clone() };
+
+        assert_ne!(digest(&base), digest(&older));
+        assert_eq!(changes(&older, &base), [("version", vec![format!("0.0.1 -> {}", env!("CARGO_PKG_VERSION"))])]);
+    }
+
+    #[test]
+    fn changed_inputs_are_named() {
+        let scratch = Scratch::new();
+        let base = key(&scratch, None, &script(), &table(&[]));
+
+        fs::write(scratch.0.join("src/main.rs"), "fn main() { println!(); }").unwrap();
+        fs::write(scratch.0.join("src/new.rs"), "").unwrap();
+        fs::remove_file(scratch.0.join("src/lib.rs")).unwrap();
+        fs::write(scratch.0.join("src/generated/schema.rs"), "// regenerated").unwrap();
+
+        let changed = key(&scratch, None, &script(), &table(&[]));
+
+        assert_ne!(digest(&base), digest(&changed));
+        assert_eq!(changes(&base, &changed), [("inputs", vec!["src/lib.rs".to_string(), "src/main.rs".to_string(), "src/new.rs".to_string()])]);
+    }
 }
@@ -1,2 +1,5 @@
+pub(crate) mod args;
 pub(crate) mod cache;
 pub(crate) mod progress;
+pub(crate) mod scheduler;
+pub(crate) mod store;
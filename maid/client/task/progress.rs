@@ -12,9 +12,9 @@ pub(crate) fn init<'p>(ticks: Vec<&str>, template: &str, tick: u64) -> &'p Progr
         let tick_str: Vec<&str> = ticks.into_iter().map(|item| fmtstr!("{item} ")).collect();
 
         pb.enable_steady_tick(std::time::Duration::from_millis(tick));
-        pb.set_style(ProgressStyle::with_template(template).unwrap().tick_strings(&*tick_str));
+        pb.set_style(ProgressStyle::with_template(template).unwrap().tick_strings(&tick_str));
 
-        return pb;
+        pb
     })
 }
 
@@ -0,0 +1,216 @@
+use maid::graph::{Graph, Node};
+use maid::{Error, Result};
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+struct State<R> {
+    ready: VecDeque<usize>,
+    pending: Vec<usize>,
+    running: Vec<usize>,
+    results: Vec<Option<R>>,
+    failed: Option<Error>,
+    started: usize,
+    done: usize,
+}
+
+pub(crate) struct Progress<'g> {
+    pub started: usize,
+    pub total: usize,
+    pub running: Vec<&'g str>,
+}
+
+// runs every dependency of the graph root, at most `jobs` at a time,
+// nothing new is started once a dependency has failed
+pub(crate) fn run<F, P, R>(graph: &Graph, jobs: usize, task: F, progress: P) -> Result<Vec<R>>
+where
+    F: Fn(&Node) -> Result<R> + Sync,
+    P: Fn(Progress) + Sync,
+    R: Send,
+{
+    let nodes = graph.dependencies();
+    let total = nodes.len();
+
+    let state = Mutex::new(State {
+        ready: nodes.iter().enumerate().filter(|(_, node)| node.depends.is_empty()).map(|(id, _)| id).collect(),
+        pending: nodes.iter().map(|node| node.depends.len()).collect(),
+        running: vec![],
+        results: nodes.iter().map(|_| None).collect(),
+        failed: None,
+        started: 0,
+        done: 0,
+    });
+
+    let signal = Condvar::new();
+    let report = |state: &State<R>| {
+        progress(Progress {
+            total,
+            started: state.started,
+            running: state.running.iter().map(|id| nodes[*id].name.as_str()).collect(),
+        })
+    };
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.max(1).min(total.max(1)) {
+            scope.spawn(|| loop {
+                let id = {
+                    let mut guard = state.lock().unwrap();
+
+                    loop {
+                        if guard.done == total || (guard.failed.is_some() && guard.running.is_empty()) {
+                            signal.notify_all();
+                            return;
+                        }
+                        if guard.failed.is_some() {
+                            guard = signal.wait(guard).unwrap();
+                            continue;
+                        }
+                        if let Some(id) = guard.ready.pop_front() {
+                            guard.started += 1;
+                            guard.running.push(id);
+                            report(&guard);
+                            break id;
+                        }
+                        guard = signal.wait(guard).unwrap();
+                    }
+                };
+
+                let result = task(&nodes[id]);
+
+                let mut guard = state.lock().unwrap();
+                guard.done += 1;
+                guard.running.retain(|item| *item != id);
+
+                match result {
+                    Ok(result) => guard.results[id] = Some(result),
+                    Err(err) => {
+                        guard.failed.get_or_insert(err);
+                    }
+                }
+
+                for dependent in graph.dependents(id) {
+                    if dependent < total {
+                        guard.pending[dependent] -= 1;
+                        if guard.pending[dependent] == 0 {
+                            guard.ready.push_back(dependent);
+                        }
+                    }
+                }
+
+                report(&guard);
+                signal.notify_all();
+            });
+        }
+    });
+
+    let state = state.into_inner().unwrap();
+
+    match state.failed {
+        Some(err) => Err(err),
+        None => Ok(state.results.into_iter().flatten().collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maid::models::shared::Maidfile;
+    use serde_json::{json, Map, Value};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Barrier,
+        },
+        time::Duration,
+    };
+
+    fn graph(tasks: &[(&str, &[&str])]) -> Graph {
+        let tasks: Map<String, Value> = tasks.iter().map(|(name, depends)| (name.to_string(), json!({ "script": "true", "depends": depends }))).collect();
+        let maidfile: Maidfile<Value> = serde_json::from_value(json!({ "tasks": tasks })).unwrap();
+
+        Graph::new(&maidfile, "root").unwrap()
+    }
+
+    #[test]
+    fn runs_shared_dependencies_once_and_in_order() {
+        let graph = graph(&[("root", &["left", "right"]), ("left", &["base"]), ("right", &["base"]), ("base", &[])]);
+        let ran = Mutex::new(vec![]);
+
+        let results = run(
+            &graph,
+            4,
+            |node| {
+                ran.lock().unwrap().push(node.name.clone());
+                Ok(node.name.clone())
+            },
+            |_| {},
+        )
+        .unwrap();
+
+        let ran = ran.into_inner().unwrap();
+        assert_eq!(ran.len(), 3);
+        assert_eq!(ran[0], "base");
+        assert_eq!(results, ["base", "left", "right"]);
+    }
+
+    #[test]
+    fn never_runs_more_than_jobs_at_once() {
+        let graph = graph(&[("root", &["a", "b", "c", "d", "e", "f"]), ("a", &[]), ("b", &[]), ("c", &[]), ("d", &[]), ("e", &[]), ("f", &[])]);
+        let (running, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+
+        run(
+            &graph,
+            2,
+            |_| {
+                peak.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            },
+            |progress| assert!(progress.running.len() <= 2),
+        )
+        .unwrap();
+
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn runs_independent_dependencies_concurrently() {
+        let graph = graph(&[("root", &["a", "b", "c"]), ("a", &[]), ("b", &[]), ("c", &[])]);
+        let barrier = Barrier::new(3);
+
+        // only returns once all three are running at the same time
+        let results = run(
+            &graph,
+            3,
+            |_| {
+                barrier.wait();
+                Ok(())
+            },
+            |_| {},
+        );
+
+        assert!(results.is_ok());
+    }
+
+    #[test]
+    fn starts_nothing_after_a_failure() {
+        let graph = graph(&[("root", &["broken", "other", "after"]), ("broken", &[]), ("other", &[]), ("after", &["broken"])]);
+        let ran = Mutex::new(vec![]);
+
+        let result = run(
+            &graph,
+            1,
+            |node| {
+                ran.lock().unwrap().push(node.name.clone());
+                match node.name.as_str() {
+                    "broken" => Err(Error::Status(String::from("broken failed"))),
+                    _ => Ok(()),
+                }
+            },
+            |_| {},
+        );
+
+        assert!(matches!(result, Err(Error::Status(message)) if message == "broken failed"));
+        assert_eq!(ran.into_inner().unwrap(), ["broken"]);
+    }
+}
@@ -0,0 +1,425 @@
+use global_placeholders::global;
+use maid::{
+    archive,
+    log::prelude::*,
+    models::client::{CacheConfig, CacheEntry, CacheKey, EntryKind},
+};
+
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, Error, ErrorKind, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use tar::EntryType;
+
+// the store lives in the task's working dir, like its targets and inputs
+pub(crate) fn object_path(dir: &Path, hash: &str) -> PathBuf { dir.join(global!("maid.objects_dir")).join(hash) }
+
+fn config_path(dir: &Path, task: &str) -> PathBuf { dir.join(global!("maid.cache_dir", task)).join(format!("{task}.toml")) }
+
+#[cfg(unix)]
+pub(crate) fn mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+pub(crate) fn mode(metadata: &fs::Metadata) -> u32 { if metadata.permissions().readonly() { 0o444 } else { 0o644 } }
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(unix)]
+fn symlink(link: &str, path: &Path) -> io::Result<()> { std::os::unix::fs::symlink(link, path) }
+
+#[cfg(windows)]
+fn symlink(link: &str, path: &Path) -> io::Result<()> { std::os::windows::fs::symlink_file(link, path) }
+
+fn store_object(dir: &Path, path: &Path) -> io::Result<(String, u64)> {
+    let mut hasher = blake3::Hasher::new();
+    let size = io::copy(&mut File::open(path)?, &mut hasher)?;
+    let hash = hasher.finalize().to_hex().to_string();
+    let object = object_path(dir, &hash);
+
+    if !object.exists() {
+        let temp = object.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        fs::copy(path, &temp)?;
+        fs::rename(&temp, &object)?;
+        debug!("stored object {hash}");
+    } else {
+        debug!("reused object {hash}");
+    }
+
+    Ok((hash, size))
+}
+
+pub(crate) fn insert_object(dir: &Path, hash: &str, reader: &mut impl Read) -> io::Result<()> {
+    let object = object_path(dir, hash);
+
+    if object.exists() {
+        io::copy(reader, &mut io::sink())?;
+        return Ok(());
+    }
+
+    fs::create_dir_all(dir.join(global!("maid.objects_dir")))?;
+
+    let temp = object.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+    let mut hasher = blake3::Hasher::new();
+    let mut file = File::create(&temp)?;
+    let mut buffer = [0u8; 65536];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+    }
+
+    if hasher.finalize().to_hex().as_str() != hash {
+        fs::remove_file(&temp)?;
+        return Err(Error::new(ErrorKind::InvalidData, format!("object {hash} does not match its contents")));
+    }
+
+    fs::rename(&temp, &object)
+}
+
+// entries are named relative to the working dir
+fn walk(dir: &Path, relative: &Path, entries: &mut Vec<CacheEntry>) -> io::Result<()> {
+    let path = dir.join(relative);
+    let metadata = fs::symlink_metadata(&path)?;
+    let name = relative.to_string_lossy().replace('\\', "/");
+
+    if metadata.file_type().is_symlink() {
+        entries.push(CacheEntry {
+            path: name,
+            kind: EntryKind::Symlink,
+            mode: 0,
+            size: 0,
+            hash: None,
+            link: Some(fs::read_link(&path)?.to_string_lossy().into_owned()),
+        });
+    } else if metadata.is_dir() {
+        entries.push(CacheEntry {
+            path: name,
+            kind: EntryKind::Directory,
+            mode: mode(&metadata),
+            size: 0,
+            hash: None,
+            link: None,
+        });
+
+        let mut children = fs::read_dir(&path)?.collect::<Result<Vec<_>, _>>()?;
+        children.sort_by_key(|entry| entry.file_name());
+
+        for child in children {
+            walk(dir, &relative.join(child.file_name()), entries)?;
+        }
+    } else {
+        let (hash, size) = store_object(dir, &path)?;
+        entries.push(CacheEntry {
+            path: name,
+            kind: EntryKind::File,
+            mode: mode(&metadata),
+            size,
+            hash: Some(hash),
+            link: None,
+        });
+    }
+
+    Ok(())
+}
+
+pub(crate) fn read(dir: &Path, task: &str) -> Option<CacheConfig> {
+    let contents = fs::read_to_string(config_path(dir, task)).ok()?;
+
+    match toml::from_str::<CacheConfig>(&contents) {
+        Ok(config) => Some(config),
+        Err(err) => {
+            warn!(%err, "Cannot read cache config, ignoring");
+            None
+        }
+    }
+}
+
+pub(crate) fn save(dir: &Path, task: &str, targets: &[String], key: &CacheKey) -> io::Result<CacheConfig> {
+    fs::create_dir_all(dir.join(global!("maid.objects_dir")))?;
+    fs::create_dir_all(dir.join(global!("maid.cache_dir", task)))?;
+
+    let mut entries = vec![];
+    for target in targets {
+        walk(dir, Path::new(target), &mut entries)?;
+    }
+
+    let hash = super::cache::digest(key);
+    let config = CacheConfig {
+        target: targets.to_vec(),
+        hash: hash.clone(),
+        key: key.clone(),
+        entries,
+    };
+
+    write(dir, task, &config)?;
+    debug!("added hash for {task} -> {hash}");
+
+    Ok(config)
+}
+
+pub(crate) fn write(dir: &Path, task: &str, config: &CacheConfig) -> io::Result<()> {
+    fs::create_dir_all(dir.join(global!("maid.cache_dir", task)))?;
+
+    match toml::to_string(config) {
+        Ok(contents) => fs::write(config_path(dir, task), contents),
+        Err(err) => Err(Error::new(ErrorKind::InvalidData, err)),
+    }
+}
+
+fn relative(path: &Path) -> bool { !path.as_os_str().is_empty() && path.components().all(|item| matches!(item, Component::Normal(_) | Component::CurDir)) }
+
+// a manifest may only write below the targets it names
+pub(crate) fn contained(config: &CacheConfig) -> io::Result<()> {
+    if let Some(target) = config.target.iter().find(|target| !relative(Path::new(target))) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("cache target '{target}' must be a relative path inside the project")));
+    }
+
+    let mut links = HashSet::new();
+
+    for entry in &config.entries {
+        let path = Path::new(&entry.path);
+
+        if !relative(path) || !config.target.iter().any(|target| path.starts_with(target)) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("cache entry '{}' is outside of the cached targets", entry.path)));
+        }
+
+        // the same rules as any other archive, a symlink may not lead the restore out of the project either
+        let kind = match entry.kind {
+            EntryKind::File => EntryType::Regular,
+            EntryKind::Directory => EntryType::Directory,
+            EntryKind::Symlink => EntryType::Symlink,
+        };
+
+        if let Err(reason) = archive::entry(path, kind, entry.link.as_deref().map(Path::new), &mut links) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("cache entry '{}' is unsafe ({reason})", entry.path)));
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn restore(dir: &Path, config: &CacheConfig) -> io::Result<()> {
+    contained(config)?;
+
+    for entry in config.entries.iter().filter(|entry| entry.kind == EntryKind::File) {
+        let hash = entry.hash.as_deref().unwrap_or_default();
+        if !object_path(dir, hash).is_file() {
+            return Err(Error::new(ErrorKind::NotFound, format!("missing object {hash} for '{}'", entry.path)));
+        }
+    }
+
+    for target in &config.target {
+        let path = dir.join(target);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&path)?,
+            Ok(_) => fs::remove_file(&path)?,
+            Err(_) => {}
+        }
+    }
+
+    for entry in &config.entries {
+        let path = dir.join(&entry.path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match entry.kind {
+            EntryKind::Directory => fs::create_dir_all(&path)?,
+            EntryKind::Symlink => symlink(entry.link.as_deref().unwrap_or_default(), &path)?,
+            EntryKind::File => {
+                fs::copy(object_path(dir, entry.hash.as_deref().unwrap_or_default()), &path)?;
+                set_mode(&path, entry.mode)?;
+            }
+        }
+    }
+
+    // directories last, a read-only directory would reject its children
+    for entry in config.entries.iter().rev().filter(|entry| entry.kind == EntryKind::Directory) {
+        set_mode(&dir.join(&entry.path), entry.mode)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn remove(dir: &Path, task: &str) -> io::Result<()> { fs::remove_dir_all(dir.join(global!("maid.cache_dir", task))) }
+
+pub(crate) fn size(config: &CacheConfig, target: &str) -> u64 {
+    let prefix = format!("{}/", target.trim_end_matches('/'));
+    config.entries.iter().filter(|entry| entry.path == target || entry.path.starts_with(&prefix)).map(|entry| entry.size).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Self {
+            crate::globals::init();
+            let dir = std::env::temp_dir().join(format!("maid-store-{}", uuid::Uuid::new_v4().simple()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) { let _ = fs::remove_dir_all(&self.0); }
+    }
+
+    fn entry(path: &str, kind: EntryKind, link: Option<&str>) -> CacheEntry {
+        CacheEntry {
+            path: path.to_string(),
+            kind,
+            mode: 0o644,
+            size: 0,
+            hash: None,
+            link: link.map(str::to_string),
+        }
+    }
+
+    fn config(target: &[&str], entries: Vec<CacheEntry>) -> CacheConfig {
+        CacheConfig {
+            target: target.iter().map(|target| target.to_string()).collect(),
+            hash: String::new(),
+            key: CacheKey::default(),
+            entries,
+        }
+    }
+
+    #[test]
+    fn contained_accepts_entries_below_their_targets() {
+        let config = config(
+            &["out", "dist/app"],
+            vec![
+                entry("out", EntryKind::Directory, None),
+                entry("out/bin", EntryKind::File, None),
+                entry("out/current", EntryKind::Symlink, Some("bin")),
+                entry("dist/app/index.js", EntryKind::File, None),
+            ],
+        );
+
+        assert!(contained(&config).is_ok());
+    }
+
+    #[test]
+    fn contained_refuses_targets_outside_the_project() {
+        for target in ["/etc", "../out", "out/../../etc", ""] {
+            assert!(contained(&config(&[target], vec![])).is_err(), "target '{target}' should be refused");
+        }
+    }
+
+    #[test]
+    fn contained_refuses_entries_outside_the_targets() {
+        for path in ["src/main.rs", "/out/bin", "out/../src/main.rs", "output/bin"] {
+            let config = config(&["out"], vec![entry(path, EntryKind::File, None)]);
+            assert!(contained(&config).is_err(), "entry '{path}' should be refused");
+        }
+    }
+
+    #[test]
+    fn contained_refuses_symlinks_leading_out() {
+        let escape = config(&["out"], vec![entry("out/link", EntryKind::Symlink, Some("../../etc"))]);
+        assert!(contained(&escape).is_err());
+
+        let absolute = config(&["out"], vec![entry("out/link", EntryKind::Symlink, Some("/etc/passwd"))]);
+        assert!(contained(&absolute).is_err());
+
+        // writing through a link that was restored earlier would land wherever it points
+        let below = config(&["out"], vec![entry("out/link", EntryKind::Symlink, Some("dir")), entry("out/link/file", EntryKind::File, None)]);
+        assert!(contained(&below).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restores_targets_exactly() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let scratch = Scratch::new();
+        let dir = &scratch.0;
+        let out = dir.join("out");
+
+        fs::create_dir_all(out.join("empty")).unwrap();
+        fs::write(out.join("run.sh"), "#!/bin/sh\n").unwrap();
+        fs::write(out.join("secret"), "hidden").unwrap();
+        std::os::unix::fs::symlink("run.sh", out.join("current")).unwrap();
+        set_mode(&out.join("run.sh"), 0o755).unwrap();
+        set_mode(&out.join("secret"), 0o600).unwrap();
+        set_mode(&out.join("empty"), 0o700).unwrap();
+        set_mode(&out, 0o750).unwrap();
+
+        let saved = save(dir, "build", &["out".to_string()], &CacheKey::default()).unwrap();
+        let read = read(dir, "build").unwrap();
+        assert_eq!(read.hash, saved.hash);
+        assert_eq!(read.entries.len(), 5);
+
+        // whatever the build left behind since is replaced, not merged
+        fs::write(out.join("secret"), "changed").unwrap();
+        fs::write(out.join("stray"), "left over").unwrap();
+        fs::remove_file(out.join("current")).unwrap();
+
+        restore(dir, &read).unwrap();
+
+        let mode = |path: &str| fs::symlink_metadata(dir.join(path)).unwrap().permissions().mode() & 0o7777;
+
+        assert_eq!(fs::read_to_string(out.join("run.sh")).unwrap(), "#!/bin/sh\n");
+        assert_eq!(fs::read_to_string(out.join("secret")).unwrap(), "hidden");
+        assert!(!out.join("stray").exists());
+        assert_eq!(fs::read_link(out.join("current")).unwrap(), Path::new("run.sh"));
+        assert!(out.join("empty").is_dir());
+        assert_eq!(mode("out"), 0o750);
+        assert_eq!(mode("out/empty"), 0o700);
+        assert_eq!(mode("out/run.sh"), 0o755);
+        assert_eq!(mode("out/secret"), 0o600);
+    }
+
+    #[test]
+    fn restore_leaves_targets_alone_when_objects_are_missing() {
+        let scratch = Scratch::new();
+        let dir = &scratch.0;
+
+        fs::create_dir_all(dir.join("out")).unwrap();
+        fs::write(dir.join("out/file"), "built").unwrap();
+
+        let saved = save(dir, "build", &["out".to_string()], &CacheKey::default()).unwrap();
+        fs::remove_dir_all(dir.join(global!("maid.objects_dir"))).unwrap();
+        fs::write(dir.join("out/file"), "rebuilt").unwrap();
+
+        assert_eq!(restore(dir, &saved).unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(fs::read_to_string(dir.join("out/file")).unwrap(), "rebuilt");
+    }
+
+    #[test]
+    fn restore_refuses_uncontained_manifests_before_touching_anything() {
+        let scratch = Scratch::new();
+        let dir = &scratch.0;
+
+        fs::write(dir.join("keep"), "untouched").unwrap();
+
+        let config = config(&["keep"], vec![entry("keep/../../evil", EntryKind::File, None)]);
+        assert_eq!(restore(dir, &config).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(fs::read_to_string(dir.join("keep")).unwrap(), "untouched");
+    }
+}
@@ -0,0 +1,55 @@
+use crate::config::Config;
+
+use maid::log::prelude::*;
+use serde::Serialize;
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+#[derive(Default, Serialize)]
+pub struct Event<'a> {
+    pub token: &'a str,
+    pub action: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+fn write(path: &Path, line: String) {
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+
+    let written = OpenOptions::new().create(true).append(true).open(path).and_then(|mut file| file.write_all(format!("{line}\n").as_bytes()));
+
+    if let Err(err) = written {
+        warn!(%err, "unable to write audit log {}", path.display());
+    }
+}
+
+// one json object per line, the file is only ever opened for appending. events come from async handlers,
+// so the write happens on the blocking pool instead of the runtime's worker threads
+pub fn record(config: &Config, event: Event) {
+    let line = Line { time: chrono::Utc::now().to_rfc3339(), event };
+    let path = config.server.audit_log.clone();
+
+    let line = match serde_json::to_string(&line) {
+        Ok(line) => line,
+        Err(err) => return warn!(%err, "unable to encode audit event"),
+    };
+
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => drop(runtime.spawn_blocking(move || write(&path, line))),
+        Err(_) => write(&path, line),
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{audit, config::Config};
+
+use maid::log::prelude::*;
+use rocket::{http::Status, outcome::Outcome, request::FromRequest, Request};
+use serde::Deserialize;
+use std::{fmt, marker::PhantomData};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Scope {
+    Health,
+    Run,
+    RunImage(String),
+    CacheRead,
+    CacheWrite,
+    Admin,
+}
+
+impl TryFrom<String> for Scope {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "health" => Ok(Scope::Health),
+            "run" => Ok(Scope::Run),
+            "cache:read" => Ok(Scope::CacheRead),
+            "cache:write" => Ok(Scope::CacheWrite),
+            "admin" => Ok(Scope::Admin),
+            _ => match value.strip_prefix("run:") {
+                Some(image) if !image.is_empty() => Ok(Scope::RunImage(image.to_string())),
+                _ => Err(format!("unknown scope '{value}', expected health, run, run:<image>, cache:read, cache:write or admin")),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Health => f.write_str("health"),
+            Scope::Run => f.write_str("run"),
+            Scope::RunImage(image) => write!(f, "run:{image}"),
+            Scope::CacheRead => f.write_str("cache:read"),
+            Scope::CacheWrite => f.write_str("cache:write"),
+            Scope::Admin => f.write_str("admin"),
+        }
+    }
+}
+
+// image scopes may end in `*` to allow every tag or repository under a prefix
+fn image_matches(pattern: &str, image: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => image.starts_with(prefix),
+        None => pattern == image,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl Token {
+    fn grants(&self, required: &Scope) -> bool {
+        self.scopes.iter().any(|scope| match (scope, required) {
+            (Scope::Admin, _) => true,
+            (Scope::Run | Scope::RunImage(_), Scope::Health) => true,
+            (Scope::Run, Scope::RunImage(_)) => true,
+            (Scope::RunImage(pattern), Scope::RunImage(image)) => image_matches(pattern, image),
+            (Scope::RunImage(_), Scope::Run) => true,
+            (scope, required) => scope == required,
+        })
+    }
+
+    pub fn can_run(&self, image: &str) -> bool { self.grants(&Scope::RunImage(image.to_string())) }
+
+    pub fn admin(&self) -> bool { self.scopes.contains(&Scope::Admin) }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Token {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        let config = match request.rocket().state::<Config>() {
+            Some(config) => config,
+            None => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        let bearer = match request.headers().get_one("Authorization").and_then(|value| value.strip_prefix("Bearer ")) {
+            Some(bearer) => bearer,
+            None => return Outcome::Error((Status::Unauthorized, ())),
+        };
+
+        // blake3 digests compare in constant time, so timing never tells how much of a token matched
+        let digest = blake3::hash(bearer.as_bytes());
+
+        match config.tokens.iter().find(|token| blake3::hash(token.token.as_bytes()) == digest) {
+            Some(token) if token.expired() => {
+                warn!("rejected expired token '{}'", token.name);
+                Outcome::Error((Status::Unauthorized, ()))
+            }
+            Some(token) => Outcome::Success(Token {
+                name: token.name.clone(),
+                scopes: token.scopes.clone(),
+            }),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+pub trait Requirement {
+    fn scope() -> Scope;
+}
+
+pub struct Health;
+pub struct Run;
+pub struct CacheRead;
+pub struct CacheWrite;
+
+impl Requirement for Health {
+    fn scope() -> Scope { Scope::Health }
+}
+
+impl Requirement for Run {
+    fn scope() -> Scope { Scope::Run }
+}
+
+impl Requirement for CacheRead {
+    fn scope() -> Scope { Scope::CacheRead }
+}
+
+impl Requirement for CacheWrite {
+    fn scope() -> Scope { Scope::CacheWrite }
+}
+
+/// A token that holds the scope `R` requires, anything else is answered with 403.
+pub struct Scoped<R: Requirement> {
+    pub token: Token,
+    requirement: PhantomData<R>,
+}
+
+#[rocket::async_trait]
+impl<'r, R: Requirement> FromRequest<'r> for Scoped<R> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        let token = match Token::from_request(request).await {
+            Outcome::Success(token) => token,
+            Outcome::Error(error) => return Outcome::Error(error),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        let scope = R::scope();
+
+        if token.grants(&scope) {
+            return Outcome::Success(Scoped { token, requirement: PhantomData });
+        }
+
+        if let Some(config) = request.rocket().state::<Config>() {
+            audit::record(
+                config,
+                audit::Event {
+                    token: &token.name,
+                    action: "denied",
+                    detail: Some(&format!("{} {} needs scope {scope}", request.method(), request.uri().path())),
+                    ..Default::default()
+                },
+            );
+        }
+
+        warn!("token '{}' lacks scope {scope}", token.name);
+        Outcome::Error((Status::Forbidden, ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(scopes: &[&str]) -> Token {
+        Token {
+            name: "ci".to_string(),
+            scopes: scopes.iter().map(|scope| Scope::try_from(scope.to_string()).unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn scopes_parse_and_print_back() {
+        for scope in ["health", "run", "run:alpine", "run:ghcr.io/org/*", "cache:read", "cache:write", "admin"] {
+            assert_eq!(Scope::try_from(scope.to_string()).unwrap().to_string(), scope);
+        }
+
+        for scope in ["", "run:", "cache", "root", "Run"] {
+            assert!(Scope::try_from(scope.to_string()).is_err(), "{scope} should not parse");
+        }
+    }
+
+    #[test]
+    fn admin_grants_everything() {
+        let admin = token(&["admin"]);
+
+        for scope in [Scope::Health, Scope::Run, Scope::RunImage("any".to_string()), Scope::CacheRead, Scope::CacheWrite, Scope::Admin] {
+            assert!(admin.grants(&scope), "admin should grant {scope}");
+        }
+
+        assert!(admin.admin());
+        assert!(admin.can_run("anything:latest"));
+    }
+
+    #[test]
+    fn run_grants_health_and_every_image() {
+        let run = token(&["run"]);
+
+        assert!(run.grants(&Scope::Run));
+        assert!(run.grants(&Scope::Health));
+        assert!(run.can_run("alpine"));
+        assert!(!run.grants(&Scope::CacheRead));
+        assert!(!run.grants(&Scope::CacheWrite));
+        assert!(!run.grants(&Scope::Admin));
+        assert!(!run.admin());
+    }
+
+    #[test]
+    fn image_scopes_only_run_their_images() {
+        let exact = token(&["run:alpine:3.20"]);
+
+        // an image scope is enough to open a run, the image itself is checked separately
+        assert!(exact.grants(&Scope::Run));
+        assert!(exact.grants(&Scope::Health));
+        assert!(exact.can_run("alpine:3.20"));
+        assert!(!exact.can_run("alpine:3.21"));
+        assert!(!exact.can_run("alpine"));
+        assert!(!exact.grants(&Scope::CacheRead));
+        assert!(!exact.grants(&Scope::Admin));
+
+        let wildcard = token(&["run:ghcr.io/org/*"]);
+
+        assert!(wildcard.can_run("ghcr.io/org/builder:1"));
+        assert!(wildcard.can_run("ghcr.io/org/"));
+        assert!(!wildcard.can_run("ghcr.io/other/builder"));
+        assert!(!wildcard.can_run("docker.io/ghcr.io/org/builder"));
+    }
+
+    #[test]
+    fn cache_scopes_are_separate() {
+        let read = token(&["cache:read"]);
+
+        assert!(read.grants(&Scope::CacheRead));
+        assert!(!read.grants(&Scope::CacheWrite));
+        assert!(!read.grants(&Scope::Health));
+        assert!(!read.grants(&Scope::Run));
+        assert!(!read.can_run("alpine"));
+
+        let write = token(&["cache:write"]);
+
+        assert!(write.grants(&Scope::CacheWrite));
+        assert!(!write.grants(&Scope::CacheRead));
+    }
+
+    #[test]
+    fn health_grants_nothing_else() {
+        let health = token(&["health"]);
+
+        assert!(health.grants(&Scope::Health));
+        assert!(!health.grants(&Scope::Run));
+        assert!(!health.can_run("alpine"));
+        assert!(!health.grants(&Scope::CacheRead));
+        assert!(!health.grants(&Scope::Admin));
+    }
+
+    #[test]
+    fn scopes_add_up() {
+        let token = token(&["run:alpine", "cache:read"]);
+
+        assert!(token.can_run("alpine"));
+        assert!(!token.can_run("ubuntu"));
+        assert!(token.grants(&Scope::CacheRead));
+        assert!(!token.grants(&Scope::CacheWrite));
+    }
+}
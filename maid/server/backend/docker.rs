@@ -0,0 +1,215 @@
+use super::{ExecutionBackend, Workspace};
+use crate::{jobs::Job, settings::RunSettings};
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use futures_core::Stream;
+use futures_util::stream::{StreamExt, TryStreamExt};
+use macros_rs::{exp::ternary, fmt::string};
+use maid::{
+    log::prelude::*,
+    protocol::{Level, Response},
+};
+use std::collections::HashMap;
+
+use bollard::{
+    container::{Config, DownloadFromContainerOptions, ListContainersOptions, RemoveContainerOptions, StatsOptions, UploadToContainerOptions},
+    errors::Error,
+    exec::{CreateExecOptions, StartExecResults},
+    image::CreateImageOptions,
+    models::HostConfig,
+    Docker,
+};
+
+pub struct DockerBackend {
+    docker: anyhow::Result<Docker>,
+}
+
+pub async fn concat_byte_stream<S>(s: S) -> Result<Vec<u8>, Error>
+where
+    S: Stream<Item = Result<Bytes, Error>>,
+{
+    s.try_fold(Vec::new(), |mut acc, chunk| async move {
+        acc.extend_from_slice(&chunk[..]);
+        Ok(acc)
+    })
+    .await
+}
+
+pub async fn list(docker: &Docker) -> anyhow::Result<Vec<String>> {
+    let mut filter: HashMap<String, Vec<String>> = HashMap::new();
+    let mut container_list: Vec<String> = vec![];
+
+    filter.insert(String::from("status"), vec![String::from("running")]);
+    let containers =
+        &docker
+            .list_containers(Some(ListContainersOptions {
+                all: true,
+                filters: filter,
+                ..Default::default()
+            }))
+            .await?;
+
+    if containers.is_empty() {
+        Ok(container_list)
+    } else {
+        for container in containers {
+            let container_id = container.id.as_ref().unwrap();
+            let stream = &mut docker.stats(container_id, Some(StatsOptions { stream: false, ..Default::default() })).take(1);
+
+            while let Some(Ok(_)) = stream.next().await {
+                container_list.push(format!("{}:{}", container.names.as_ref().unwrap()[0], container.image.as_ref().unwrap_or(&"".to_string())));
+            }
+        }
+
+        Ok(container_list)
+    }
+}
+
+impl DockerBackend {
+    pub fn new(docker: anyhow::Result<Docker>) -> Self { Self { docker } }
+
+    fn docker(&self) -> anyhow::Result<&Docker> { self.docker.as_ref().map_err(|err| anyhow!("docker is unavailable on this server ({err:#})")) }
+
+    async fn exec(&self, id: &str, cmd: Vec<&str>, dir: Option<&str>, env: &[String], user: Option<&str>, output: &(dyn Fn(String) + Send + Sync)) -> anyhow::Result<Option<i64>> {
+        let socket = self.docker()?;
+
+        let exec = socket
+            .create_exec(
+                id,
+                CreateExecOptions {
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    cmd: Some(cmd),
+                    env: Some(env.iter().map(String::as_str).collect()),
+                    working_dir: dir,
+                    user,
+                    ..Default::default()
+                },
+            )
+            .await?
+            .id;
+
+        if let StartExecResults::Attached { output: mut stream, .. } = socket.start_exec(&exec, None).await? {
+            while let Some(message) = stream.next().await {
+                match message {
+                    Ok(message) => output(message.to_string()),
+                    Err(err) => warn!("{err}"),
+                }
+            }
+        }
+
+        Ok(socket.inspect_exec(&exec).await?.exit_code)
+    }
+}
+
+#[rocket::async_trait]
+impl ExecutionBackend for DockerBackend {
+    async fn engine(&self) -> anyhow::Result<String> {
+        let info = self.docker()?.version().await?;
+        Ok(format!("Docker v{} (build {})", info.version.unwrap_or_default(), info.git_commit.unwrap_or_default()))
+    }
+
+    async fn active(&self) -> anyhow::Result<Vec<String>> { list(self.docker()?).await }
+
+    async fn prepare(&self, job: &Job, image: &str, options: &RunSettings) -> anyhow::Result<Workspace> {
+        let socket = self.docker()?;
+
+        let image_config = CreateImageOptions {
+            from_image: image,
+            ..Default::default()
+        };
+
+        let mut pull = socket.create_image(Some(image_config), None, None);
+
+        while let Some(message) = pull.next().await {
+            let message = message.map_err(|err| anyhow!("unable to pull image {image}: {err}"))?;
+            let formatted = format!(
+                "{} {}",
+                message.status.clone().unwrap_or_else(|| string!("Waiting")),
+                message.progress.clone().unwrap_or_else(|| string!(""))
+            );
+
+            job.emit(Response::message(Level::Docker, formatted));
+        }
+        info!("image ready");
+
+        let config = Config {
+            image: Some(image.to_string()),
+            tty: Some(true),
+            working_dir: Some(options.workdir.clone()),
+            host_config: Some(HostConfig {
+                nano_cpus: options.nano_cpus,
+                memory: options.memory,
+                network_mode: Some(options.network.clone()),
+                binds: ternary!(options.binds.is_empty(), None, Some(options.binds.clone())),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let id = socket.create_container::<&str, String>(None, config).await?.id;
+        info!("created container");
+
+        let workspace = Workspace {
+            id,
+            workdir: options.workdir.clone(),
+            user: options.user.clone(),
+        };
+
+        if let Err(err) = socket.start_container::<String>(&workspace.id, None).await {
+            let _ = self.cleanup(&workspace).await;
+            return Err(err.into());
+        }
+        info!("started container");
+
+        Ok(workspace)
+    }
+
+    async fn upload(&self, workspace: &Workspace, tree: Vec<u8>) -> anyhow::Result<()> {
+        let upload_options = UploadToContainerOptions {
+            path: workspace.workdir.as_str(),
+            ..Default::default()
+        };
+
+        self.docker()?.upload_to_container(&workspace.id, Some(upload_options), tree.into()).await?;
+        info!("wrote tarfile to container");
+
+        // uploads belong to root, a task running as another user has to own its workdir
+        if let Some(user) = &workspace.user {
+            let code = self.exec(&workspace.id, vec!["chown", "-R", user, &workspace.workdir], None, &[], None, &|_| {}).await?;
+            if code != Some(0) {
+                warn!("unable to hand {} to user {user}", workspace.workdir);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn run(&self, workspace: &Workspace, dir: &str, shell: &str, script: &str, env: &[String], output: &(dyn Fn(String) + Send + Sync)) -> anyhow::Result<Option<i64>> {
+        let script = format!("{}/{script}", workspace.workdir);
+        let dir = format!("{}/{dir}", workspace.workdir);
+
+        self.exec(&workspace.id, vec![shell, &script], Some(&dir), env, workspace.user.as_deref(), output).await
+    }
+
+    async fn download(&self, workspace: &Workspace, path: &str) -> anyhow::Result<Vec<u8>> {
+        let res = self.docker()?.download_from_container(
+            &workspace.id,
+            Some(DownloadFromContainerOptions {
+                path: format!("{}/{path}", workspace.workdir),
+            }),
+        );
+
+        Ok(concat_byte_stream(res).await?)
+    }
+
+    async fn cleanup(&self, workspace: &Workspace) -> anyhow::Result<()> {
+        self.docker()?
+            .remove_container(&workspace.id, Some(RemoveContainerOptions { force: true, ..Default::default() }))
+            .await?;
+
+        info!("removed old container");
+        Ok(())
+    }
+}
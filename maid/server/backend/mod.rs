@@ -0,0 +1,61 @@
+pub mod docker;
+pub mod process;
+
+use crate::{
+    config::{BackendKind, Config},
+    jobs::Job,
+    settings::RunSettings,
+};
+
+use maid::log::prelude::*;
+use std::sync::Arc;
+
+/// Where a job runs, handed back to the backend that prepared it for every later step.
+pub struct Workspace {
+    /// Container id, or the directory of a process workspace.
+    pub id: String,
+    /// Where pushed files land, the script runs and pulls are relative to.
+    pub workdir: String,
+    pub user: Option<String>,
+}
+
+/// Runs jobs somewhere. Every job gets a workspace of its own, which is cleaned up however the job ends.
+#[rocket::async_trait]
+pub trait ExecutionBackend: Send + Sync {
+    /// Names the engine and its version for the health route.
+    async fn engine(&self) -> anyhow::Result<String>;
+
+    /// Workspaces that are running right now, for the health route.
+    async fn active(&self) -> anyhow::Result<Vec<String>>;
+
+    async fn prepare(&self, job: &Job, image: &str, options: &RunSettings) -> anyhow::Result<Workspace>;
+
+    /// Extracts the pushed tree into the workdir.
+    async fn upload(&self, workspace: &Workspace, tree: Vec<u8>) -> anyhow::Result<()>;
+
+    /// Runs a script file with the shell from `dir`, both relative to the workdir. Every piece of output is handed to `output` as it arrives.
+    async fn run(&self, workspace: &Workspace, dir: &str, shell: &str, script: &str, env: &[String], output: &(dyn Fn(String) + Send + Sync)) -> anyhow::Result<Option<i64>>;
+
+    /// Tars a path relative to the workdir, named after its last component like `docker cp` does.
+    async fn download(&self, workspace: &Workspace, path: &str) -> anyhow::Result<Vec<u8>>;
+
+    async fn cleanup(&self, workspace: &Workspace) -> anyhow::Result<()>;
+}
+
+pub fn create(config: &Config) -> Arc<dyn ExecutionBackend> {
+    match config.backend.kind {
+        BackendKind::Docker => {
+            let socket = config.docker();
+
+            if let Err(err) = &socket {
+                warn!("{err:#}");
+            }
+
+            Arc::new(docker::DockerBackend::new(socket))
+        }
+        BackendKind::Process => {
+            warn!("running jobs as plain processes in {}, without any container isolation", config.backend.workspaces.display());
+            Arc::new(process::ProcessBackend::new(&config.backend.workspaces, config.server.limits(), config.backend.allow_degraded))
+        }
+    }
+}
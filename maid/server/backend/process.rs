@@ -0,0 +1,194 @@
+use super::{ExecutionBackend, Workspace};
+use crate::{jobs::Job, settings::RunSettings};
+
+use anyhow::{bail, Context};
+use macros_rs::fmt::string;
+use maid::{
+    archive::{self, Limits},
+    log::prelude::*,
+    protocol::{Level, Response},
+};
+
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+};
+
+use tar::Builder;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+};
+
+/// Runs scripts straight on the host, each job in a directory of its own. Meant for hosts
+/// without Docker and for testing, the image and container limits do not apply.
+pub struct ProcessBackend {
+    dir: PathBuf,
+    limits: Limits,
+    // taken once at startup, scripts never see anything else of the server's environment
+    path: OsString,
+    degraded: bool,
+}
+
+// kills whatever the script left running in the background once it is done or cancelled
+#[cfg(unix)]
+struct Group(i32);
+
+#[cfg(unix)]
+impl Drop for Group {
+    fn drop(&mut self) { unsafe { libc::killpg(self.0, libc::SIGKILL) }; }
+}
+
+impl ProcessBackend {
+    pub fn new(dir: &Path, limits: Limits, degraded: bool) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            limits,
+            path: std::env::var_os("PATH").unwrap_or_default(),
+            degraded,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl ExecutionBackend for ProcessBackend {
+    async fn engine(&self) -> anyhow::Result<String> { Ok(format!("Process (workspaces in {})", self.dir.display())) }
+
+    async fn active(&self) -> anyhow::Result<Vec<String>> {
+        let mut active = vec![];
+
+        for entry in fs::read_dir(&self.dir).into_iter().flatten().flatten() {
+            active.push(entry.file_name().to_string_lossy().into_owned());
+        }
+
+        Ok(active)
+    }
+
+    async fn prepare(&self, job: &Job, image: &str, options: &RunSettings) -> anyhow::Result<Workspace> {
+        if !options.binds.is_empty() {
+            bail!("the process backend cannot mount volumes");
+        }
+
+        if options.user.is_some() {
+            bail!("the process backend cannot run tasks as another user");
+        }
+
+        let unsupported: Vec<String> = [
+            (options.nano_cpus.is_some(), string!("cpu limit")),
+            (options.memory.is_some(), string!("memory limit")),
+            (options.network == "none", string!("network none")),
+        ]
+        .into_iter()
+        .filter_map(|(set, item)| set.then_some(item))
+        .collect();
+
+        // isolation a task asked for is never dropped unless the server says so
+        if !unsupported.is_empty() && !self.degraded {
+            bail!("the process backend cannot enforce {}, set backend.allow_degraded to run without it", unsupported.join(", "));
+        }
+
+        let ignored: Vec<String> = std::iter::once(format!("image {image}")).chain(unsupported).collect();
+        job.emit(Response::message(Level::Warning, format!("running on the host, ignoring {}", ignored.join(", "))));
+
+        let workdir = self.dir.join(&job.id);
+        fs::create_dir_all(&workdir).with_context(|| format!("unable to create workspace {}", workdir.display()))?;
+        info!("created workspace {}", workdir.display());
+
+        let workdir = workdir.display().to_string();
+        Ok(Workspace { id: workdir.clone(), workdir, user: None })
+    }
+
+    async fn upload(&self, workspace: &Workspace, tree: Vec<u8>) -> anyhow::Result<()> {
+        let roots = [PathBuf::from(&workspace.workdir)];
+
+        archive::extract(|| Ok(tree.as_slice()), &roots, &self.limits, &|path: &Path| Ok((0, path.to_path_buf())))?;
+        info!("wrote tarfile to workspace");
+
+        Ok(())
+    }
+
+    async fn run(&self, workspace: &Workspace, dir: &str, shell: &str, script: &str, env: &[String], output: &(dyn Fn(String) + Send + Sync)) -> anyhow::Result<Option<i64>> {
+        let root = Path::new(&workspace.workdir);
+        let mut process = Command::new(shell);
+
+        if !root.join(dir).is_dir() {
+            bail!("directory '{dir}' does not exist in the workspace");
+        }
+
+        // only the task's own environment, not the server's
+        process
+            .arg(root.join(script))
+            .current_dir(root.join(dir))
+            .env_clear()
+            .env("PATH", &self.path)
+            .env("HOME", &workspace.workdir)
+            .envs(env.iter().filter_map(|var| var.split_once('=')))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        #[cfg(unix)]
+        process.process_group(0);
+
+        let mut child = process.spawn().with_context(|| format!("unable to start {shell}"))?;
+
+        #[cfg(unix)]
+        let _group = child.id().map(|pid| Group(pid as i32));
+
+        let mut stdout = child.stdout.take().map(|stdout| BufReader::new(stdout).lines());
+        let mut stderr = child.stderr.take().map(|stderr| BufReader::new(stderr).lines());
+
+        while stdout.is_some() || stderr.is_some() {
+            let (line, out) = tokio::select! {
+                line = async { stdout.as_mut().unwrap().next_line().await }, if stdout.is_some() => (line?, true),
+                line = async { stderr.as_mut().unwrap().next_line().await }, if stderr.is_some() => (line?, false),
+            };
+
+            match (line, out) {
+                (Some(line), _) => output(format!("{line}\n")),
+                (None, true) => stdout = None,
+                (None, false) => stderr = None,
+            }
+        }
+
+        let status = child.wait().await?;
+        Ok(status.code().map(i64::from))
+    }
+
+    async fn download(&self, workspace: &Workspace, path: &str) -> anyhow::Result<Vec<u8>> {
+        let relative = Path::new(path);
+
+        if !relative.components().all(|item| matches!(item, Component::Normal(_) | Component::CurDir)) {
+            bail!("pull '{path}' must be a relative path inside the workspace");
+        }
+
+        // symlinks are archived as links, never followed out of the workspace
+        let root = fs::canonicalize(&workspace.workdir)?;
+        let source = fs::canonicalize(root.join(relative)).with_context(|| format!("nothing to pull at '{path}'"))?;
+
+        if !source.starts_with(&root) {
+            bail!("pull '{path}' points outside the workspace");
+        }
+
+        let name = source.file_name().map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+        let mut tar = Builder::new(Vec::new());
+        tar.follow_symlinks(false);
+
+        match source.is_dir() {
+            true => tar.append_dir_all(&name, &source)?,
+            false => tar.append_path_with_name(&source, &name)?,
+        }
+
+        Ok(tar.into_inner()?)
+    }
+
+    async fn cleanup(&self, workspace: &Workspace) -> anyhow::Result<()> {
+        fs::remove_dir_all(&workspace.workdir).with_context(|| format!("unable to remove workspace {}", workspace.workdir))?;
+        info!("removed workspace {}", workspace.workdir);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{
+    audit,
+    auth::{CacheRead, CacheWrite, Scoped},
+    config::Config,
+};
+
+use maid::log::prelude::*;
+use rocket::{data::ToByteUnit, fs::NamedFile, get, head, http::Status, put, Data, State};
+use std::path::PathBuf;
+use tokio::fs;
+
+fn artifact(config: &Config, key: &str) -> Result<PathBuf, Status> {
+    match key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => Ok(config.server.cache.join(format!("{key}.tgz"))),
+        false => Err(Status::BadRequest),
+    }
+}
+
+#[head("/api/cache/<key>")]
+pub async fn exists(key: &str, config: &State<Config>, _auth: Scoped<CacheRead>) -> Status {
+    match artifact(config, key) {
+        Ok(path) if path.is_file() => Status::Ok,
+        Ok(_) => Status::NotFound,
+        Err(status) => status,
+    }
+}
+
+#[get("/api/cache/<key>")]
+pub async fn download(key: &str, config: &State<Config>, _auth: Scoped<CacheRead>) -> Result<NamedFile, Status> {
+    let path = artifact(config, key)?;
+
+    match NamedFile::open(&path).await {
+        Ok(file) => Ok(file),
+        Err(_) => Err(Status::NotFound),
+    }
+}
+
+#[put("/api/cache/<key>", data = "<data>")]
+pub async fn upload(key: &str, data: Data<'_>, config: &State<Config>, auth: Scoped<CacheWrite>) -> Status {
+    let path = match artifact(config, key) {
+        Ok(path) => path,
+        Err(status) => return status,
+    };
+
+    if path.is_file() {
+        return Status::Ok;
+    }
+
+    if let Err(err) = fs::create_dir_all(&config.server.cache).await {
+        warn!(%err, "unable to create cache dir");
+        return Status::InternalServerError;
+    }
+
+    let temp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+
+    match data.open(config.server.cache_limit().bytes()).into_file(&temp).await {
+        Ok(file) if file.is_complete() => match fs::rename(&temp, &path).await {
+            Ok(_) => {
+                info!("stored cache artifact {key}");
+                audit::record(
+                    config,
+                    audit::Event {
+                        token: &auth.token.name,
+                        action: "cache.write",
+                        detail: Some(key),
+                        ..Default::default()
+                    },
+                );
+                Status::Created
+            }
+            Err(err) => {
+                warn!(%err, "unable to store cache artifact");
+                Status::InternalServerError
+            }
+        },
+        Ok(_) => {
+            let _ = fs::remove_file(&temp).await;
+            Status::PayloadTooLarge
+        }
+        Err(err) => {
+            warn!(%err, "unable to receive cache artifact");
+            let _ = fs::remove_file(&temp).await;
+            Status::InternalServerError
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use crate::{auth::Scope, settings};
+
+use anyhow::{anyhow, bail, Context};
+use bollard::{Docker, API_DEFAULT_VERSION};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, ValueEnum};
+use global_placeholders::global;
+use macros_rs::fmt::string;
+use maid::{archive::Limits, log::prelude::*};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+const DEFAULT_CONFIG: &str = "maid-server.toml";
+
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Server config file (maid-server.toml when present)
+    #[arg(short, long, env = "MAID_SERVER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to bind to
+    #[arg(short, long, env = "MAID_SERVER_ADDRESS")]
+    pub address: Option<IpAddr>,
+
+    /// Port to listen on
+    #[arg(short, long, env = "MAID_SERVER_PORT")]
+    pub port: Option<u16>,
+
+    /// TLS certificate chain (PEM)
+    #[arg(long, env = "MAID_SERVER_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// TLS private key (PEM)
+    #[arg(long, env = "MAID_SERVER_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Where jobs run, in docker containers or as plain processes
+    #[arg(long, env = "MAID_SERVER_BACKEND")]
+    pub backend: Option<BackendKind>,
+
+    /// How to reach the Docker daemon
+    #[arg(long, env = "MAID_DOCKER_MODE")]
+    pub docker: Option<DockerMode>,
+
+    /// Docker socket path or host:port
+    #[arg(long, env = "MAID_DOCKER_ADDRESS")]
+    pub docker_address: Option<String>,
+
+    /// Containers that may run at the same time, further jobs wait in a queue
+    #[arg(long, env = "MAID_SERVER_MAX_CONTAINERS")]
+    pub max_containers: Option<usize>,
+
+    /// API tokens with admin scope as name=token, may be repeated
+    #[arg(long = "token", env = "MAID_SERVER_TOKENS", value_delimiter = ',')]
+    pub tokens: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: Server,
+    pub backend: Backend,
+    pub docker: DockerConfig,
+    pub jobs: Jobs,
+    pub containers: Containers,
+    pub tokens: Vec<ApiToken>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Server {
+    pub address: IpAddr,
+    pub port: u16,
+    pub tls: Option<Tls>,
+    pub audit_log: PathBuf,
+    pub transfers: PathBuf,
+    pub store: PathBuf,
+    /// Where build cache artifacts uploaded by clients are kept.
+    pub cache: PathBuf,
+    /// Largest cache artifact a client may upload, like 512m or 2g.
+    pub max_cache_size: String,
+    /// Most entries a push may hold.
+    pub max_push_entries: usize,
+    /// Largest a push may unpack to, like 512m or 4g.
+    pub max_push_size: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Docker,
+    Process,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Backend {
+    pub kind: BackendKind,
+    pub workspaces: PathBuf,
+    /// Lets the process backend run tasks that asked for no network or cpu and memory limits, without them.
+    pub allow_degraded: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DockerMode {
+    #[default]
+    Socket,
+    Http,
+    Tls,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DockerConfig {
+    pub mode: DockerMode,
+    pub address: Option<String>,
+    pub timeout: u64,
+    pub ca: Option<PathBuf>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Jobs {
+    pub max_containers: usize,
+    pub dir: PathBuf,
+    pub keep_days: u64,
+}
+
+/// What tasks may ask of their container, anything outside it is refused before the job is queued.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Containers {
+    pub max_cpus: Option<f64>,
+    pub max_memory: Option<String>,
+    pub network: String,
+    pub networks: Vec<String>,
+    pub volumes: Vec<String>,
+    pub users: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiToken {
+    pub name: String,
+    pub token: String,
+    #[serde(default, deserialize_with = "expiry")]
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(default = "admin")]
+    pub scopes: Vec<Scope>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self {
+            address: IpAddr::from([127, 0, 0, 1]),
+            port: 3500,
+            tls: None,
+            audit_log: PathBuf::from(global!("maid.audit_log")),
+            transfers: PathBuf::from(global!("maid.transfers_dir")),
+            store: PathBuf::from(global!("maid.store_dir")),
+            cache: PathBuf::from(global!("maid.cache_dir")),
+            max_cache_size: string!("2g"),
+            max_push_entries: 100_000,
+            max_push_size: string!("4g"),
+        }
+    }
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self {
+            kind: BackendKind::Docker,
+            workspaces: PathBuf::from(global!("maid.workspaces_dir")),
+            allow_degraded: false,
+        }
+    }
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        Self {
+            mode: DockerMode::Socket,
+            address: None,
+            timeout: 120,
+            ca: None,
+            cert: None,
+            key: None,
+        }
+    }
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self {
+            max_containers: 4,
+            dir: PathBuf::from(global!("maid.jobs_dir")),
+            keep_days: 7,
+        }
+    }
+}
+
+impl Default for Containers {
+    fn default() -> Self {
+        Self {
+            max_cpus: None,
+            max_memory: None,
+            network: string!("bridge"),
+            networks: vec![string!("bridge"), string!("none")],
+            volumes: vec![],
+            users: vec![],
+        }
+    }
+}
+
+impl Server {
+    /// What a pushed tree has to stay under, checked on the manifest and again before it is unpacked.
+    pub fn limits(&self) -> Limits {
+        Limits {
+            entries: self.max_push_entries,
+            bytes: settings::bytes(&self.max_push_size).unwrap_or_default() as u64,
+        }
+    }
+
+    pub fn cache_limit(&self) -> u64 { settings::bytes(&self.max_cache_size).unwrap_or_default() as u64 }
+}
+
+impl ApiToken {
+    pub fn expired(&self) -> bool { self.expires.is_some_and(|expires| expires <= Utc::now()) }
+}
+
+fn admin() -> Vec<Scope> { vec![Scope::Admin] }
+
+// accepts toml datetimes, rfc3339 strings and plain dates (expiring at the start of that day)
+fn expiry<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    let value = match Option::<toml::Value>::deserialize(deserializer)? {
+        Some(toml::Value::String(value)) => value,
+        Some(toml::Value::Datetime(value)) => value.to_string(),
+        Some(value) => return Err(D::Error::custom(format!("expected a date, found {}", value.type_str()))),
+        None => return Ok(None),
+    };
+
+    parse_expiry(&value).map(Some).map_err(D::Error::custom)
+}
+
+fn parse_expiry(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()),
+        Err(_) => Err(format!("invalid expiry '{value}', use a date (2025-01-31) or an RFC 3339 timestamp")),
+    }
+}
+
+fn exists(path: &Path, what: &str) -> anyhow::Result<()> {
+    match path.is_file() {
+        true => Ok(()),
+        false => bail!("{what} '{}' does not exist", path.display()),
+    }
+}
+
+impl Config {
+    pub fn load(cli: &Cli) -> anyhow::Result<Self> {
+        let path = cli.config.clone().or_else(|| Some(PathBuf::from(DEFAULT_CONFIG)).filter(|path| path.is_file()));
+
+        let mut config: Config = match &path {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path).with_context(|| format!("unable to read config '{}'", path.display()))?;
+                toml::from_str(&contents).with_context(|| format!("invalid config '{}'", path.display()))?
+            }
+            None => Config::default(),
+        };
+
+        if let Some(address) = cli.address {
+            config.server.address = address;
+        }
+
+        if let Some(port) = cli.port {
+            config.server.port = port;
+        }
+
+        if let (Some(cert), Some(key)) = (&cli.tls_cert, &cli.tls_key) {
+            config.server.tls = Some(Tls { cert: cert.clone(), key: key.clone() });
+        }
+
+        if let Some(kind) = cli.backend {
+            config.backend.kind = kind;
+        }
+
+        if let Some(mode) = cli.docker {
+            config.docker.mode = mode;
+        }
+
+        if let Some(address) = &cli.docker_address {
+            config.docker.address = Some(address.clone());
+        }
+
+        if let Some(max) = cli.max_containers {
+            config.jobs.max_containers = max;
+        }
+
+        for item in cli.tokens.iter().filter(|item| !item.trim().is_empty()) {
+            match item.split_once('=') {
+                Some((name, token)) => config.tokens.push(ApiToken {
+                    name: name.trim().to_string(),
+                    token: token.trim().to_string(),
+                    expires: None,
+                    scopes: admin(),
+                }),
+                None => bail!("invalid token '{item}', expected name=token"),
+            }
+        }
+
+        config.validate()?;
+
+        match &path {
+            Some(path) => info!("loaded config {}", path.display()),
+            None => info!("no config file found, using defaults"),
+        }
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.server.port == 0 {
+            bail!("server port cannot be 0");
+        }
+
+        if self.server.max_push_entries == 0 {
+            bail!("server.max_push_entries must be above 0");
+        }
+
+        if settings::bytes(&self.server.max_push_size).is_none() {
+            bail!("server.max_push_size '{}' is not a size like 512m or 4g", self.server.max_push_size);
+        }
+
+        if settings::bytes(&self.server.max_cache_size).is_none() {
+            bail!("server.max_cache_size '{}' is not a size like 512m or 2g", self.server.max_cache_size);
+        }
+
+        if let Some(tls) = &self.server.tls {
+            exists(&tls.cert, "TLS certificate")?;
+            exists(&tls.key, "TLS key")?;
+        }
+
+        match self.docker.mode {
+            DockerMode::Socket => {}
+            DockerMode::Http => {
+                self.docker.address.as_ref().ok_or_else(|| anyhow!("docker mode 'http' needs an address"))?;
+            }
+            DockerMode::Tls => {
+                self.docker.address.as_ref().ok_or_else(|| anyhow!("docker mode 'tls' needs an address"))?;
+                for (path, what) in [(&self.docker.ca, "docker CA"), (&self.docker.cert, "docker certificate"), (&self.docker.key, "docker key")] {
+                    match path {
+                        Some(path) => exists(path, what)?,
+                        None => bail!("docker mode 'tls' needs ca, cert and key"),
+                    }
+                }
+            }
+        }
+
+        if self.jobs.max_containers == 0 {
+            bail!("jobs.max_containers must be at least 1");
+        }
+
+        let containers = &self.containers;
+
+        if containers.max_cpus.is_some_and(|cpus| cpus.is_nan() || cpus <= 0.0) {
+            bail!("containers.max_cpus must be above 0");
+        }
+
+        if let Some(memory) = &containers.max_memory {
+            settings::bytes(memory).ok_or_else(|| anyhow!("containers.max_memory '{memory}' is not a size like 512m or 4g"))?;
+        }
+
+        if !containers.networks.contains(&containers.network) {
+            bail!("containers.network '{}' must be one of containers.networks", containers.network);
+        }
+
+        if self.tokens.is_empty() {
+            bail!("no API tokens configured, add [[tokens]] to the config or pass --token name=token");
+        }
+
+        let mut names = HashSet::new();
+        let mut tokens = HashSet::new();
+
+        for token in &self.tokens {
+            if token.name.is_empty() || token.token.is_empty() {
+                bail!("API tokens need a name and a token");
+            }
+            if !names.insert(&token.name) {
+                bail!("duplicate API token name '{}'", token.name);
+            }
+            if !tokens.insert(&token.token) {
+                bail!("API token '{}' reuses the secret of another token", token.name);
+            }
+            if token.scopes.is_empty() {
+                bail!("API token '{}' has no scopes", token.name);
+            }
+            if token.expired() {
+                warn!("API token '{}' has already expired", token.name);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn docker(&self) -> anyhow::Result<Docker> {
+        let docker = &self.docker;
+
+        let socket = match (docker.mode, &docker.address) {
+            (DockerMode::Socket, Some(path)) => Docker::connect_with_socket(path, docker.timeout, API_DEFAULT_VERSION)?,
+            (DockerMode::Socket, None) => Docker::connect_with_socket_defaults()?,
+            (DockerMode::Http, Some(address)) => Docker::connect_with_http(address, docker.timeout, API_DEFAULT_VERSION)?,
+            (DockerMode::Tls, Some(address)) => match (&docker.key, &docker.cert, &docker.ca) {
+                (Some(key), Some(cert), Some(ca)) => Docker::connect_with_ssl(address, key, cert, ca, docker.timeout, API_DEFAULT_VERSION)?,
+                _ => bail!("docker mode 'tls' needs ca, cert and key"),
+            },
+            (_, None) => bail!("docker needs an address"),
+        };
+
+        Ok(socket)
+    }
+}
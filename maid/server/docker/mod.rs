@@ -1,2 +0,0 @@
-pub mod run;
-pub mod container;
\ No newline at end of file
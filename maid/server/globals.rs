@@ -2,4 +2,10 @@ use global_placeholders::init;
 
 pub fn init() {
     init!("maid.temp_dir", "/usr/tmp/maid");
+    init!("maid.cache_dir", "/usr/tmp/maid/cache");
+    init!("maid.audit_log", "/usr/tmp/maid/audit.log");
+    init!("maid.transfers_dir", "/usr/tmp/maid/transfers");
+    init!("maid.store_dir", "/usr/tmp/maid/store");
+    init!("maid.jobs_dir", "/usr/tmp/maid/jobs");
+    init!("maid.workspaces_dir", "/usr/tmp/maid/workspaces");
 }
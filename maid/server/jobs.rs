@@ -0,0 +1,370 @@
+use crate::auth::Token;
+
+use anyhow::Context;
+use maid::{
+    log::prelude::*,
+    protocol::{self, JobStatus, JobSummary, Response},
+};
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tokio::sync::{broadcast, watch};
+use uuid::Uuid;
+
+const SUMMARY: &str = "job.json";
+const LOG: &str = "log.jsonl";
+const ARTIFACT: &str = "artifact.tgz";
+
+/// Every job the server knows about, finished ones are loaded back from disk on startup.
+#[derive(Clone)]
+pub struct Jobs {
+    dir: PathBuf,
+    keep_days: u64,
+    jobs: Arc<Mutex<HashMap<String, Arc<Job>>>>,
+}
+
+pub struct Job {
+    pub id: String,
+    dir: PathBuf,
+    state: Mutex<State>,
+    disk: Arc<Disk>,
+    events: broadcast::Sender<Event>,
+    cancel: watch::Sender<bool>,
+}
+
+struct State {
+    summary: JobSummary,
+    lines: u64,
+}
+
+// what a job still has to write, queued under the state lock so lines keep their order
+struct Disk {
+    id: String,
+    dir: PathBuf,
+    log: Mutex<Option<File>>,
+    pending: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    lines: Vec<String>,
+    summary: Option<String>,
+}
+
+#[derive(Clone)]
+pub enum Event {
+    Line(u64, String),
+    Finished,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> { mutex.lock().unwrap_or_else(|err| err.into_inner()) }
+
+// jobs are driven from async tasks, so disk writes go to the blocking pool like the audit log
+fn blocking(disk: &Arc<Disk>) {
+    let disk = Arc::clone(disk);
+
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => drop(runtime.spawn_blocking(move || disk.flush())),
+        Err(_) => disk.flush(),
+    }
+}
+
+// finished jobs older than this are removed
+fn cutoff(keep_days: u64) -> i64 { protocol::now() - (keep_days as i64) * 24 * 60 * 60 * 1000 }
+
+fn remove(dir: &Path, id: &str) {
+    match fs::remove_dir_all(dir) {
+        Ok(_) => debug!("removed expired job {id}"),
+        Err(err) => warn!(%err, "unable to remove expired job {id}"),
+    }
+}
+
+fn lines(path: &Path) -> u64 {
+    match File::open(path) {
+        Ok(file) => BufReader::new(file).lines().count() as u64,
+        Err(_) => 0,
+    }
+}
+
+impl Jobs {
+    pub fn load(dir: &Path, keep_days: u64) -> Self {
+        let mut jobs = HashMap::new();
+        let cutoff = cutoff(keep_days);
+
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            let summary = match fs::read_to_string(path.join(SUMMARY)).ok().and_then(|contents| serde_json::from_str::<JobSummary>(&contents).ok()) {
+                Some(summary) => summary,
+                None => continue,
+            };
+
+            if summary.finished.is_some_and(|finished| finished < cutoff) {
+                remove(&path, &summary.id);
+                continue;
+            }
+
+            let job = Job::new(&path, summary.clone(), lines(&path.join(LOG)));
+
+            // whatever was running when the server stopped is gone with its container
+            if !summary.status.finished() {
+                job.finish(JobStatus::Cancelled, None, None);
+            }
+
+            jobs.insert(summary.id.clone(), Arc::new(job));
+        }
+
+        if !jobs.is_empty() {
+            info!("loaded {} jobs from {}", jobs.len(), dir.display());
+        }
+
+        Self {
+            dir: dir.to_path_buf(),
+            keep_days,
+            jobs: Arc::new(Mutex::new(jobs)),
+        }
+    }
+
+    /// Forgets finished jobs that outlived `keep_days` and removes them from disk.
+    pub fn prune(&self) {
+        let cutoff = cutoff(self.keep_days);
+
+        let expired: Vec<Arc<Job>> = {
+            let mut jobs = lock(&self.jobs);
+            let ids: Vec<String> = jobs.values().filter(|job| job.summary().finished.is_some_and(|finished| finished < cutoff)).map(|job| job.id.clone()).collect();
+            ids.iter().filter_map(|id| jobs.remove(id)).collect()
+        };
+
+        for job in expired {
+            remove(&job.dir, &job.id);
+        }
+    }
+
+    pub fn create(&self, task: &str, project: &str, token: &Token, image: &str) -> anyhow::Result<Arc<Job>> {
+        let id = Uuid::new_v4().simple().to_string()[..12].to_string();
+        let dir = self.dir.join(&id);
+
+        fs::create_dir_all(&dir).with_context(|| format!("unable to create job directory {}", dir.display()))?;
+
+        let summary = JobSummary {
+            id: id.clone(),
+            task: task.to_string(),
+            project: project.to_string(),
+            token: token.name.clone(),
+            image: image.to_string(),
+            status: JobStatus::Queued,
+            code: None,
+            created: protocol::now(),
+            finished: None,
+            artifact: None,
+        };
+
+        let job = Arc::new(Job::new(&dir, summary, 0));
+        job.save();
+
+        lock(&self.jobs).insert(id, Arc::clone(&job));
+        Ok(job)
+    }
+
+    /// Jobs are only visible to the token that started them, and to admins.
+    pub fn get(&self, id: &str, token: &Token) -> Option<Arc<Job>> { lock(&self.jobs).get(id).filter(|job| job.visible(token)).cloned() }
+
+    pub fn list(&self, token: &Token) -> Vec<JobSummary> {
+        let mut jobs: Vec<JobSummary> = lock(&self.jobs).values().filter(|job| job.visible(token)).map(|job| job.summary()).collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created));
+        jobs
+    }
+
+    /// Finds the job that kept an artifact by its hash, for clients resuming a pull.
+    pub fn artifact(&self, hash: &str, token: &Token) -> Option<Arc<Job>> {
+        lock(&self.jobs)
+            .values()
+            .filter(|job| job.visible(token))
+            .find(|job| job.summary().artifact.as_deref() == Some(hash))
+            .cloned()
+    }
+}
+
+impl Job {
+    fn new(dir: &Path, summary: JobSummary, lines: u64) -> Self {
+        let (events, _) = broadcast::channel(1024);
+        let (cancel, _) = watch::channel(false);
+        let log = OpenOptions::new().create(true).append(true).open(dir.join(LOG));
+
+        if let Err(err) = &log {
+            warn!(%err, "unable to open log for job {}", summary.id);
+        }
+
+        Self {
+            id: summary.id.clone(),
+            dir: dir.to_path_buf(),
+            disk: Arc::new(Disk {
+                id: summary.id.clone(),
+                dir: dir.to_path_buf(),
+                log: Mutex::new(log.ok()),
+                pending: Mutex::default(),
+            }),
+            state: Mutex::new(State { summary, lines }),
+            events,
+            cancel,
+        }
+    }
+
+    fn visible(&self, token: &Token) -> bool { token.admin() || lock(&self.state).summary.token == token.name }
+
+    fn save(&self) {
+        let contents = serde_json::to_string_pretty(&lock(&self.state).summary).unwrap_or_default();
+        lock(&self.disk.pending).summary = Some(contents);
+        blocking(&self.disk);
+    }
+
+    pub fn summary(&self) -> JobSummary { lock(&self.state).summary.clone() }
+
+    pub fn artifact_path(&self) -> PathBuf { self.dir.join(ARTIFACT) }
+
+    /// Appends a frame to the log and hands it to everyone attached.
+    pub fn emit(&self, response: Response) {
+        let line = response.to_json();
+
+        {
+            let mut state = lock(&self.state);
+            lock(&self.disk.pending).lines.push(line.clone());
+
+            let seq = state.lines;
+            state.lines += 1;
+
+            let _ = self.events.send(Event::Line(seq, line));
+        }
+
+        blocking(&self.disk);
+    }
+
+    pub fn start(&self) {
+        lock(&self.state).summary.status = JobStatus::Running;
+        self.save();
+    }
+
+    pub fn finish(&self, status: JobStatus, code: Option<i64>, artifact: Option<String>) {
+        {
+            let mut state = lock(&self.state);
+            state.summary.status = status;
+            state.summary.code = code;
+            state.summary.artifact = artifact;
+            state.summary.finished = Some(protocol::now());
+        }
+
+        self.save();
+
+        // sent under the lock so subscribers never see it before the last line
+        let _state = lock(&self.state);
+        let _ = self.events.send(Event::Finished);
+    }
+
+    /// Live events from here on, with the number of lines already logged and whether the job is over.
+    pub fn subscribe(&self) -> (broadcast::Receiver<Event>, u64, bool) {
+        let state = lock(&self.state);
+        (self.events.subscribe(), state.lines, state.summary.status.finished())
+    }
+
+    /// Logged frames `from..to`, read back from disk once everything queued before them is written.
+    pub async fn replay(&self, from: u64, to: u64) -> io::Result<Vec<String>> {
+        if from >= to {
+            return Ok(vec![]);
+        }
+
+        let disk = Arc::clone(&self.disk);
+
+        tokio::task::spawn_blocking(move || {
+            disk.flush();
+            let file = File::open(disk.dir.join(LOG))?;
+            BufReader::new(file).lines().skip(from as usize).take((to - from) as usize).collect()
+        })
+        .await?
+    }
+
+    pub fn cancel(&self) { self.cancel.send_replace(true); }
+
+    pub fn cancelled(&self) -> watch::Receiver<bool> { self.cancel.subscribe() }
+}
+
+impl Disk {
+    // holding the log for the whole flush keeps two flushes from writing out of order
+    fn flush(&self) {
+        let mut log = lock(&self.log);
+        let pending = std::mem::take(&mut *lock(&self.pending));
+
+        if let Some(log) = &mut *log {
+            for line in pending.lines {
+                if let Err(err) = writeln!(log, "{line}") {
+                    warn!(%err, "unable to write log for job {}", self.id);
+                }
+            }
+        }
+
+        if let Some(contents) = pending.summary {
+            if let Err(err) = fs::write(self.dir.join(SUMMARY), contents) {
+                warn!(%err, "unable to save job {}", self.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+
+    #[test]
+    fn prune_removes_jobs_that_expired_while_running() {
+        let dir = std::env::temp_dir().join(format!("maid-jobs-{}", Uuid::new_v4().simple()));
+        let token = Token { name: "ci".to_string(), scopes: vec![Scope::Run] };
+
+        // nothing is kept once finished
+        let jobs = Jobs::load(&dir, 0);
+        let finished = jobs.create("build", "app", &token, "alpine").unwrap();
+        let running = jobs.create("test", "app", &token, "alpine").unwrap();
+
+        finished.finish(JobStatus::Succeeded, Some(0), None);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        jobs.prune();
+
+        assert!(jobs.get(&finished.id, &token).is_none());
+        assert!(!dir.join(&finished.id).exists());
+        assert!(jobs.get(&running.id, &token).is_some());
+        assert!(dir.join(&running.id).exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn writes_land_on_disk_in_order() {
+        let dir = std::env::temp_dir().join(format!("maid-jobs-{}", Uuid::new_v4().simple()));
+        let token = Token { name: "ci".to_string(), scopes: vec![Scope::Run] };
+
+        let jobs = Jobs::load(&dir, 7);
+        let job = jobs.create("build", "app", &token, "alpine").unwrap();
+
+        let responses: Vec<Response> = (0..200).map(|index| Response::message(protocol::Level::Info, format!("line {index}"))).collect();
+        let expected: Vec<String> = responses.iter().map(|response| response.to_json()).collect();
+
+        job.start();
+        for response in responses {
+            job.emit(response);
+        }
+        job.finish(JobStatus::Succeeded, Some(0), None);
+
+        assert_eq!(job.replay(0, 200).await.unwrap(), expected);
+        assert_eq!(job.replay(150, 152).await.unwrap(), expected[150..152]);
+
+        // replaying flushed the queue, the last save wins over the earlier ones
+        let saved: JobSummary = serde_json::from_str(&fs::read_to_string(dir.join(&job.id).join(SUMMARY)).unwrap()).unwrap();
+        assert_eq!(saved.status, JobStatus::Succeeded);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,95 +1,85 @@
-mod docker;
+mod audit;
+mod auth;
+mod backend;
+mod cache;
+mod config;
 mod globals;
 mod helpers;
-
-use bollard::{Docker, API_DEFAULT_VERSION};
-use docker::container;
-use macros_rs::{exp::ternary, fmt::fmtstr};
-use maid::log::prelude::*;
-use rocket::futures::SinkExt;
-use rocket::{get, http::Status, launch, outcome::Outcome, routes, State};
-use rocket_ws::{Channel, Message, WebSocket};
-use serde::{Deserialize, Serialize};
+mod jobs;
+mod pull;
+mod queue;
+mod runner;
+mod script;
+mod session;
+mod settings;
+mod store;
+mod transfer;
+
+use clap::Parser;
+use auth::{Health, Run, Scoped};
+use config::{Cli, Config};
+use backend::ExecutionBackend;
+use jobs::Jobs;
+use queue::Queue;
+use macros_rs::exp::ternary;
+use maid::{
+    log::{layer::prelude::*, prelude::*},
+    protocol::{self, Handshake, JobSummary, Level, Response},
+};
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::{get, routes, serde::json::Json, State};
+use rocket_ws::{stream::DuplexStream, Channel, Message, WebSocket};
 use serde_json::{json, Value};
-use std::env;
-
-struct DockerState {
-    docker: Result<Docker, anyhow::Error>,
-}
-
-#[derive(Serialize, Deserialize)]
-enum Level {
-    None,
-    Fatal,
-    Docker,
-    Debug,
-    Error,
-    Notice,
-    Info,
-    Build,
-    Warning,
-    Success,
-}
-
-#[derive(Serialize, Deserialize)]
-enum Kind {
-    Done,
-    Binary,
-    Message,
-}
-
-struct Response {
-    level: Level,
-    kind: Kind,
-    message: Option<String>,
-}
-
-impl Response {
-    fn to_string(&self) -> String {
-        let json_value = serde_json::json!({
-            "kind": &self.kind,
-            "level": &self.level,
-            "message": &self.message,
-            "time": chrono::Utc::now().timestamp_millis(),
-        });
+use std::{env, sync::Arc};
 
-        serde_json::to_string(&json_value).unwrap()
-    }
+pub(crate) trait IntoMessage {
+    fn into_message(self) -> Message;
 }
 
-impl From<Response> for Message {
-    fn from(response: Response) -> Self { Message::text(response.to_string()) }
+impl IntoMessage for Response {
+    fn into_message(self) -> Message { Message::text(self.to_json()) }
 }
 
-#[derive(Debug)]
-struct Token;
-
-#[rocket::async_trait]
-impl<'r> rocket::request::FromRequest<'r> for Token {
-    type Error = ();
-
-    async fn from_request(request: &'r rocket::Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
-        let token = "test_token1".to_string();
-        let authorization_header = request.headers().get_one("Authorization");
+// the first frame must be a handshake of the same protocol version, anything else ends the session
+async fn handshake(stream: &mut DuplexStream) -> Result<bool, rocket_ws::result::Error> {
+    let frame = loop {
+        match stream.next().await {
+            Some(Ok(Message::Text(text))) => break text,
+            Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+            Some(Ok(_)) => break String::new(),
+            Some(Err(err)) => return Err(err),
+            None => return Ok(false),
+        }
+    };
 
-        if let Some(header_value) = authorization_header {
-            if header_value == fmtstr!("Bearer {token}") {
-                return Outcome::Success(Token);
+    let reason = match serde_json::from_str::<Handshake>(&frame) {
+        Ok(peer) => match peer.mismatch("client") {
+            Some(reason) => reason,
+            None => {
+                debug!("client speaks protocol v{} (maid {}, capabilities: {:?})", peer.protocol, peer.version, peer.capabilities);
+                stream.send(Message::text(serde_json::to_string(&Handshake::new()).unwrap_or_default())).await?;
+                return Ok(true);
             }
-        }
+        },
+        Err(_) => format!("expected a protocol v{} handshake, the client is likely older than the server, upgrade it", protocol::VERSION),
+    };
 
-        Outcome::Error((Status::Unauthorized, ()))
-    }
+    warn!("rejected client: {reason}");
+    stream.send(Response::message(Level::Fatal, reason).into_message()).await?;
+    stream.send(Response::done(None).into_message()).await?;
+
+    Ok(false)
 }
 
 #[get("/api/health")]
-async fn health(docker_state: &State<DockerState>, _token: Token) -> Value {
-    let socket = &docker_state.docker.as_ref().unwrap();
-    let info = socket.version().await.unwrap();
-    let containers = container::list(socket).await.unwrap();
+async fn health(backend: &State<Arc<dyn ExecutionBackend>>, auth: Scoped<Health>) -> Value {
+    debug!("health check by '{}'", auth.token.name);
 
+    let engine = backend.engine().await;
+    let healthy = helpers::os::health() && engine.is_ok();
+    let version = engine.unwrap_or_else(|err| format!("unavailable ({err:#})"));
+    let containers = backend.active().await.unwrap_or_default();
     let uptime = helpers::format::duration(helpers::os::uptime());
-    let version = format!("Docker v{} (build {})", &info.version.clone().unwrap(), &info.git_commit.clone().unwrap());
 
     json!({
         "version": {
@@ -110,7 +100,7 @@ async fn health(docker_state: &State<DockerState>, _token: Token) -> Value {
                 "hue": "green"
             },
             "healthy": {
-                "data": ternary!(helpers::os::health(), "yes", "no"),
+                "data": ternary!(healthy, "yes", "no"),
                 "hue": "cyan"
             },
             "containers": {
@@ -121,21 +111,27 @@ async fn health(docker_state: &State<DockerState>, _token: Token) -> Value {
     })
 }
 
+#[get("/api/jobs")]
+fn list_jobs(jobs: &State<Jobs>, auth: Scoped<Run>) -> Json<Vec<JobSummary>> { Json(jobs.list(&auth.token)) }
+
 #[get("/ws/gateway")]
-fn stream(ws: WebSocket, docker_state: &State<DockerState>, _token: Token) -> Channel {
-    let connect_success = Response {
-        level: Level::Success,
-        kind: Kind::Message,
-        message: Some("client connected".to_string()),
-    };
+fn stream<'r>(ws: WebSocket, backend: &'r State<Arc<dyn ExecutionBackend>>, config: &'r State<Config>, queue: &'r State<Queue>, jobs: &'r State<Jobs>, auth: Scoped<Run>) -> Channel<'r> {
+    let token = auth.token;
+    info!("client connected with token '{}'", token.name);
+
+    let connect_success = Response::message(Level::Success, "client connected");
 
     ws.channel(move |mut stream| {
         Box::pin(async move {
-            stream.send(connect_success.into()).await?;
+            if !handshake(&mut stream).await? {
+                return Ok(());
+            }
 
-            match docker::run::exec(stream, &docker_state.docker).await {
-                Ok(_) => info!("build finished"),
-                Err(_) => warn!("failed to build"),
+            stream.send(connect_success.into_message()).await?;
+
+            match session::handle(stream, backend, config, queue, jobs, &token).await {
+                Ok(_) => info!("session finished"),
+                Err(err) => warn!("session ended: {err:#}"),
             };
 
             Ok(())
@@ -143,28 +139,47 @@ fn stream(ws: WebSocket, docker_state: &State<DockerState>, _token: Token) -> Ch
     })
 }
 
-#[launch]
-#[tokio::main]
-async fn rocket() -> _ {
-    let http = true;
+#[rocket::main]
+async fn main() {
+    let cli = Cli::parse();
 
-    std::env::set_var("ROCKET_PORT", "3500");
     std::env::set_var("RUST_LOG", "rocket");
 
     globals::init();
     pretty_env_logger::init();
 
-    let socket = async move {
-        let socket = match http {
-            true => Docker::connect_with_http("100.79.107.11:4250", 120, API_DEFAULT_VERSION)?.clone(),
-            false => Docker::connect_with_socket_defaults()?.clone(),
-        };
+    // rocket logs through pretty_env_logger, maid's own events go through tracing
+    let subscriber = tracing_subscriber::registry().with(tracing::level_filters::LevelFilter::INFO).with(MaidFormatLayer::new());
+    if let Err(err) = tracing::subscriber::set_global_default(subscriber) {
+        eprintln!("unable to set up logging: {err}");
+    }
 
-        Ok::<Docker, anyhow::Error>(socket)
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("{err:#}");
+            std::process::exit(1);
+        }
     };
 
-    let docker_socket = tokio::spawn(socket);
-    let docker_socket = docker_socket.await.unwrap();
+    transfer::prune(&config.server.transfers);
+
+    let backend = backend::create(&config);
+    let mut figment = rocket::Config::figment().merge(("address", config.server.address)).merge(("port", config.server.port));
 
-    rocket::build().manage(DockerState { docker: docker_socket }).mount("/", routes![health, stream])
+    if let Some(tls) = &config.server.tls {
+        figment = figment.merge(("tls.certs", &tls.cert)).merge(("tls.key", &tls.key));
+    }
+
+    let server = rocket::custom(figment)
+        .manage(backend)
+        .manage(Queue::new(config.jobs.max_containers))
+        .manage(Jobs::load(&config.jobs.dir, config.jobs.keep_days))
+        .manage(config)
+        .mount("/", routes![health, list_jobs, stream, cache::exists, cache::download, cache::upload]);
+
+    if let Err(err) = server.launch().await {
+        tracing::error!("{err}");
+        std::process::exit(1);
+    }
 }
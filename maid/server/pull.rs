@@ -0,0 +1,133 @@
+use crate::{
+    backend::{ExecutionBackend, Workspace},
+    jobs::Job,
+};
+
+use anyhow::{anyhow, bail};
+use glob::{MatchOptions, Pattern};
+use macros_rs::{
+    exp::{ternary, then},
+    fmt::string,
+};
+use maid::{
+    log::prelude::*,
+    models::shared::Pull,
+    protocol::{Level, Response},
+};
+
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use tar::{Archive, Builder, EntryType};
+
+const WILDCARDS: &[char] = &['*', '?', '['];
+
+const MATCH: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+// everything before the first wildcard is downloaded, the rest is matched against what came back
+fn split(path: &str) -> anyhow::Result<(PathBuf, Option<Pattern>)> {
+    let relative = Path::new(path);
+
+    if !relative.components().all(|item| matches!(item, Component::Normal(_) | Component::CurDir)) {
+        bail!("pull '{path}' must be a relative path inside the workdir");
+    }
+
+    let parts: Vec<String> = relative.components().filter(|item| matches!(item, Component::Normal(_))).map(|item| item.as_os_str().to_string_lossy().into_owned()).collect();
+
+    match parts.iter().position(|part| part.contains(WILDCARDS)) {
+        None => Ok((parts.iter().collect(), None)),
+        Some(wildcard) => {
+            let pattern = Pattern::new(&parts.join("/")).map_err(|err| anyhow!("pull '{path}' is not a valid glob: {err}"))?;
+            Ok((parts[..wildcard].iter().collect(), Some(pattern)))
+        }
+    }
+}
+
+fn name(path: &Path) -> PathBuf { path.file_name().map(PathBuf::from).unwrap_or_default() }
+
+// downloads are named after their last component, the first component is swapped for the downloaded path
+fn inside(base: &Path, entry: &Path) -> PathBuf {
+    let mut components = entry.components();
+    components.next();
+    base.join(components.as_path())
+}
+
+// moves the matches of one pull into the artifact, below a directory named after its index
+fn select(artifact: &mut Builder<Vec<u8>>, index: usize, base: &Path, pattern: Option<&Pattern>, download: &[u8]) -> anyhow::Result<usize> {
+    let mut archive = Archive::new(download);
+    let mut roots: Vec<PathBuf> = vec![];
+    let mut written = 0;
+
+    if pattern.is_none() {
+        roots.push(base.to_path_buf());
+    }
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = inside(base, &entry.path()?);
+        let kind = entry.header().entry_type();
+
+        let target = match roots.iter().find(|root| path.starts_with(root)) {
+            Some(root) => Path::new(&index.to_string()).join(name(root)).join(path.strip_prefix(root)?),
+            None if pattern.is_some_and(|pattern| pattern.matches_path_with(&path, MATCH)) => {
+                then!(kind == EntryType::Directory, roots.push(path.clone()));
+                Path::new(&index.to_string()).join(name(&path))
+            }
+            None => continue,
+        };
+
+        let mut header = entry.header().clone();
+
+        match kind {
+            EntryType::Regular | EntryType::Continuous => artifact.append_data(&mut header, &target, &mut entry)?,
+            EntryType::Directory => artifact.append_data(&mut header, &target, io::empty())?,
+            EntryType::Symlink => match entry.link_name()? {
+                Some(link) => artifact.append_link(&mut header, &target, link)?,
+                None => continue,
+            },
+            _ => {
+                warn!("skipping '{}' from the pull, it is not a file, directory or symlink", path.display());
+                continue;
+            }
+        }
+
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+/// Downloads every pull of the task into one tar, none when nothing matched.
+pub async fn collect(job: &Job, backend: &dyn ExecutionBackend, workspace: &Workspace, pulls: &[Pull]) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut artifact = Builder::new(Vec::new());
+    let mut found = false;
+
+    for (index, pull) in pulls.iter().enumerate() {
+        let (base, pattern) = split(&pull.path)?;
+        let source = ternary!(base.as_os_str().is_empty(), string!("."), base.display().to_string());
+
+        let download = match backend.download(workspace, &source).await {
+            Ok(download) => download,
+            Err(err) => {
+                job.emit(Response::message(Level::Warning, format!("unable to pull '{}': {err:#}", pull.path)));
+                continue;
+            }
+        };
+
+        match select(&mut artifact, index, &base, pattern.as_ref(), &download)? {
+            0 => job.emit(Response::message(Level::Warning, format!("pull '{}' matched nothing", pull.path))),
+            _ => found = true,
+        }
+    }
+
+    match found {
+        true => Ok(Some(artifact.into_inner()?)),
+        false => Ok(None),
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tokio::sync::watch;
+
+/// Admits jobs in order while there is a free container and their project is not locked.
+#[derive(Clone)]
+pub struct Queue {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    changed: watch::Sender<u64>,
+}
+
+#[derive(Default)]
+struct State {
+    max: usize,
+    next: u64,
+    running: usize,
+    waiting: VecDeque<Job>,
+    projects: HashMap<String, Lock>,
+}
+
+#[derive(Clone)]
+struct Job {
+    id: u64,
+    project: String,
+    exclusive: bool,
+}
+
+#[derive(Default)]
+struct Lock {
+    running: usize,
+    exclusive: bool,
+}
+
+/// A place in the queue, dropping it (for example when the client leaves) gives the place up.
+pub struct Ticket {
+    id: u64,
+    queue: Queue,
+    changed: watch::Receiver<u64>,
+}
+
+/// A running job, dropping it frees the container and the project.
+pub struct Slot {
+    job: Job,
+    queue: Queue,
+}
+
+pub enum Admission {
+    Started(Slot),
+    Waiting(usize),
+}
+
+impl State {
+    // exclusive jobs need their project to themselves, others only need it not to be held exclusively
+    fn fits(&self, job: &Job) -> bool {
+        match self.projects.get(&job.project) {
+            Some(lock) if lock.exclusive => false,
+            Some(lock) if job.exclusive => lock.running == 0,
+            _ => true,
+        }
+    }
+
+    // earlier jobs go first, unless their project is busy and this one could run instead. once an
+    // exclusive job waits for its project, later jobs of that project wait behind it so it cannot starve
+    fn admissible(&self, id: u64) -> bool {
+        if self.running >= self.max {
+            return false;
+        }
+
+        let mut reserved = HashSet::new();
+
+        for job in &self.waiting {
+            if !reserved.contains(&job.project) && self.fits(job) {
+                return job.id == id;
+            }
+
+            if job.exclusive {
+                reserved.insert(&job.project);
+            }
+        }
+
+        false
+    }
+
+    fn position(&self, id: u64) -> usize { self.waiting.iter().position(|job| job.id == id).map_or(0, |index| index + 1) }
+}
+
+impl Queue {
+    pub fn new(max: usize) -> Self {
+        let (changed, _) = watch::channel(0);
+
+        Self {
+            inner: Arc::new(Inner {
+                changed,
+                state: Mutex::new(State { max, ..Default::default() }),
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> { self.inner.state.lock().unwrap_or_else(|err| err.into_inner()) }
+
+    fn notify(&self) { self.inner.changed.send_modify(|version| *version = version.wrapping_add(1)) }
+
+    pub fn enter(&self, project: &str, exclusive: bool) -> Ticket {
+        let mut state = self.state();
+        let id = state.next;
+
+        state.next += 1;
+        state.waiting.push_back(Job { id, exclusive, project: project.to_string() });
+
+        Ticket {
+            id,
+            queue: self.clone(),
+            changed: self.inner.changed.subscribe(),
+        }
+    }
+}
+
+impl Ticket {
+    /// Starts the job when it is its turn, otherwise reports its position in the queue.
+    pub fn admit(&mut self) -> Admission {
+        self.changed.borrow_and_update();
+
+        let mut state = self.queue.state();
+
+        if !state.admissible(self.id) {
+            return Admission::Waiting(state.position(self.id));
+        }
+
+        let index = state.position(self.id) - 1;
+        let job = match state.waiting.remove(index) {
+            Some(job) => job,
+            None => return Admission::Waiting(0),
+        };
+
+        let lock = state.projects.entry(job.project.clone()).or_default();
+        lock.running += 1;
+        lock.exclusive = job.exclusive;
+        state.running += 1;
+        drop(state);
+
+        // everyone behind moved up a place
+        self.queue.notify();
+
+        Admission::Started(Slot { job, queue: self.queue.clone() })
+    }
+
+    /// Resolves once a job started, finished or left the queue.
+    pub async fn changed(&mut self) { let _ = self.changed.changed().await; }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let left = {
+            let mut state = self.queue.state();
+            let before = state.waiting.len();
+            state.waiting.retain(|job| job.id != self.id);
+            state.waiting.len() != before
+        };
+
+        if left {
+            self.queue.notify();
+        }
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        {
+            let mut state = self.queue.state();
+            state.running -= 1;
+
+            if let Some(lock) = state.projects.get_mut(&self.job.project) {
+                lock.running -= 1;
+                if lock.running == 0 {
+                    state.projects.remove(&self.job.project);
+                }
+            }
+        }
+
+        self.queue.notify();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(ticket: &mut Ticket) -> Option<Slot> {
+        match ticket.admit() {
+            Admission::Started(slot) => Some(slot),
+            Admission::Waiting(_) => None,
+        }
+    }
+
+    #[test]
+    fn admits_up_to_the_limit_in_order() {
+        let queue = Queue::new(2);
+        let mut tickets: Vec<Ticket> = (0..3).map(|index| queue.enter(&format!("p{index}"), false)).collect();
+
+        assert!(started(&mut tickets[2]).is_none());
+        let first = started(&mut tickets[0]);
+        assert!(first.is_some());
+        let second = started(&mut tickets[1]);
+        assert!(second.is_some());
+        assert!(matches!(tickets[2].admit(), Admission::Waiting(1)));
+
+        drop(first);
+        assert!(started(&mut tickets[2]).is_some());
+    }
+
+    #[test]
+    fn exclusive_jobs_wait_for_their_project() {
+        let queue = Queue::new(4);
+        let mut shared = queue.enter("app", false);
+        let mut exclusive = queue.enter("app", true);
+        let mut other = queue.enter("lib", false);
+
+        let running = started(&mut shared);
+        assert!(running.is_some());
+        assert!(started(&mut exclusive).is_none());
+        assert!(started(&mut other).is_some());
+
+        drop(running);
+        assert!(started(&mut exclusive).is_some());
+    }
+
+    #[test]
+    fn waiting_exclusive_jobs_are_not_overtaken() {
+        let queue = Queue::new(4);
+        let mut first = queue.enter("app", false);
+        let mut exclusive = queue.enter("app", true);
+        let mut later = queue.enter("app", false);
+
+        let running = started(&mut first);
+        assert!(running.is_some());
+
+        // the later job would fit next to the first, but the exclusive one asked for the project before it
+        assert!(started(&mut later).is_none());
+        assert!(started(&mut exclusive).is_none());
+
+        drop(running);
+        let exclusive = started(&mut exclusive);
+        assert!(exclusive.is_some());
+        assert!(started(&mut later).is_none());
+
+        drop(exclusive);
+        assert!(started(&mut later).is_some());
+    }
+}
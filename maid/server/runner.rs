@@ -0,0 +1,274 @@
+use crate::{
+    audit,
+    auth::Token,
+    backend::{ExecutionBackend, Workspace},
+    config::Config as ServerConfig,
+    jobs::Job,
+    pull,
+    queue::{Admission, Queue, Slot},
+    script::{self, Section},
+    settings::{self, RunSettings},
+};
+use maid::{
+    archive::{self, Limits},
+    log::prelude::*,
+    models::shared::Remote,
+    protocol::{ConnectionData, JobStatus, Level, Phase, Response, Step},
+    table,
+};
+
+use flate2::{write::GzEncoder, Compression};
+use serde_json::Value;
+use std::{
+    default::Default,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+use tar::Builder;
+use tokio::sync::watch;
+
+use macros_rs::{
+    exp::{ternary, then},
+    fmt::string,
+};
+
+enum Outcome {
+    Exited { code: Option<i64>, artifact: Option<String> },
+    Cancelled,
+}
+
+/// Runs a job to the end on its own, clients only ever read what it logged.
+#[allow(clippy::too_many_arguments)]
+pub async fn run(job: Arc<Job>, backend: Arc<dyn ExecutionBackend>, settings: ServerConfig, queue: Queue, token: Token, parsed: ConnectionData<Value>, options: RunSettings, tree: Builder<Vec<u8>>) {
+    let name = &parsed.info.name;
+    let image = parsed.info.remote.image.clone();
+    let mut cancelled = job.cancelled();
+
+    let _slot = match wait(&job, &queue, &mut cancelled, parsed.info.remote.exclusive).await {
+        Some(slot) => slot,
+        None => {
+            info!("job {} left the queue (task={name})", job.id);
+            finish_cancelled(&job, &settings, &token, name, &image);
+            return;
+        }
+    };
+
+    job.start();
+    info!("starting job {} (task={name}, image={image})", job.id);
+
+    let (code, artifact) = match execute(&job, backend.as_ref(), &parsed, &options, tree, &settings.server.limits(), &mut cancelled).await {
+        Ok(Outcome::Exited { code, artifact }) => (code, artifact),
+        Ok(Outcome::Cancelled) => {
+            warn!("cancelled job {} (task={name})", job.id);
+            finish_cancelled(&job, &settings, &token, name, &image);
+            return;
+        }
+        Err(err) => {
+            warn!("job {} failed: {err:#}", job.id);
+            job.emit(Response::message(Level::Fatal, format!("{err:#}")));
+            (None, None)
+        }
+    };
+
+    info!("task {name} exited with {code:?}");
+
+    audit::record(
+        &settings,
+        audit::Event {
+            token: &token.name,
+            action: "run",
+            task: Some(name),
+            image: Some(&image),
+            exit_code: code,
+            detail: Some(&job.id),
+        },
+    );
+
+    let status = match code {
+        Some(0) => JobStatus::Succeeded,
+        _ => JobStatus::Failed,
+    };
+
+    job.finish(status, code, artifact);
+}
+
+// holds the job until it may start, none when it is cancelled while waiting
+async fn wait(job: &Job, queue: &Queue, cancelled: &mut watch::Receiver<bool>, exclusive: bool) -> Option<Slot> {
+    let mut ticket = queue.enter(&job.summary().project, exclusive);
+    let mut reported = 0;
+
+    loop {
+        then!(*cancelled.borrow_and_update(), return None);
+
+        match ticket.admit() {
+            Admission::Started(slot) => {
+                if reported > 0 {
+                    job.emit(Response::message(Level::Info, "left the queue, starting"));
+                }
+                return Some(slot);
+            }
+            Admission::Waiting(position) if position != reported => {
+                reported = position;
+                job.emit(Response::message(Level::Info, format!("waiting for a free container, position {position} in queue")));
+            }
+            Admission::Waiting(_) => {}
+        }
+
+        tokio::select! {
+            _ = ticket.changed() => {}
+            _ = cancelled.changed() => {}
+        }
+    }
+}
+
+fn finish_cancelled(job: &Job, settings: &ServerConfig, token: &Token, task: &str, image: &str) {
+    audit::record(
+        settings,
+        audit::Event {
+            token: &token.name,
+            action: "cancelled",
+            task: Some(task),
+            image: Some(image),
+            detail: Some(&job.id),
+            ..Default::default()
+        },
+    );
+
+    job.emit(Response::message(Level::Fatal, format!("job {} was cancelled", job.id)));
+    job.finish(JobStatus::Cancelled, None, None);
+}
+
+// the workspace is cleaned up however the job ends
+async fn execute(job: &Job, backend: &dyn ExecutionBackend, parsed: &ConnectionData<Value>, options: &RunSettings, tree: Builder<Vec<u8>>, limits: &Limits, cancelled: &mut watch::Receiver<bool>) -> anyhow::Result<Outcome> {
+    let workspace = backend.prepare(job, &parsed.info.remote.image, options).await?;
+    let outcome = inside(job, backend, &workspace, parsed, tree, limits, cancelled).await;
+
+    if let Err(err) = backend.cleanup(&workspace).await {
+        warn!("unable to clean up workspace {}: {err:#}", workspace.id);
+    }
+
+    outcome
+}
+
+async fn inside(job: &Job, backend: &dyn ExecutionBackend, workspace: &Workspace, parsed: &ConnectionData<Value>, mut tree: Builder<Vec<u8>>, limits: &Limits, cancelled: &mut watch::Receiver<bool>) -> anyhow::Result<Outcome> {
+    let sections = script::plan(&parsed.maidfile, &parsed.info.name)?;
+
+    // every dir points into the workspace, the server's own directories are none of the script's business
+    let workdir = PathBuf::from(&workspace.workdir);
+    let dirs = table::Dirs { current: workdir.clone(), home: workdir.clone(), project: workdir };
+    let table = table::build(parsed.maidfile.env.as_ref(), &parsed.info.args, &dirs);
+    let env = settings::environment(parsed.maidfile.env.as_ref(), &table::view(&table))?;
+
+    script::append(&mut tree, &sections, &table::view(&table))?;
+    let tree = tree.into_inner()?;
+
+    // the tree comes from a client's manifest, nothing in it may land outside the workdir
+    archive::verify(tree.as_slice(), &[PathBuf::new()], limits, &|path: &Path| Ok((0, path.to_path_buf())))?;
+    backend.upload(workspace, tree).await?;
+
+    let dependencies = sections.iter().filter(|section| section.dependency).count();
+    let start = Instant::now();
+
+    job.emit(Response::message(Level::Build, "waiting for build to finish.."));
+
+    // dependencies run one after another, the first one to fail ends the job
+    let mut code = Some(0);
+    for (id, section) in sections.iter().enumerate() {
+        let place = ternary!(section.dir.is_empty(), string!(""), format!(" (in {})", section.dir));
+
+        match section.dependency {
+            true => job.emit(Response::message(Level::Build, format!("running dependency {}{place}", section.task))),
+            false if dependencies > 0 => job.emit(Response::message(
+                Level::Success,
+                format!("finished {dependencies} {} in {:.2?}", ternary!(dependencies > 1, "dependencies", "dependency"), start.elapsed()),
+            )),
+            false => {}
+        }
+
+        code = match steps(job, backend, workspace, &parsed.info.remote, &env, id, section, cancelled).await? {
+            Outcome::Exited { code, .. } => code,
+            Outcome::Cancelled => return Ok(Outcome::Cancelled),
+        };
+
+        if code != Some(0) {
+            then!(section.dependency, job.emit(Response::message(Level::Error, format!("dependency {} failed", section.task))));
+            break;
+        }
+    }
+
+    let remote = &parsed.info.remote;
+    let pulls = remote.pull.iter().map(|pull| pull.path.as_str()).collect::<Vec<_>>().join(", ");
+
+    if code != Some(0) && !remote.pull_on_failure {
+        info!("skipped pulling [{pulls}], task failed");
+        return Ok(Outcome::Exited { code, artifact: None });
+    }
+
+    let bytes = match pull::collect(job, backend, workspace, &remote.pull).await? {
+        Some(bytes) => bytes,
+        None => return Ok(Outcome::Exited { code, artifact: None }),
+    };
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+
+    encoder.write_all(&bytes)?;
+    let compressed_data = encoder.finish()?;
+
+    // kept with the job so it can be pulled by whoever attaches, as often as needed
+    let artifact = blake3::hash(&compressed_data).to_hex().to_string();
+    std::fs::write(job.artifact_path(), &compressed_data)?;
+    info!("stored artifact {artifact}, from [{pulls}]");
+
+    Ok(Outcome::Exited { code, artifact: Some(artifact) })
+}
+
+// like a local run every line is its own step, the first failure ends the task unless it continues on error
+#[allow(clippy::too_many_arguments)]
+async fn steps(job: &Job, backend: &dyn ExecutionBackend, workspace: &Workspace, remote: &Remote, env: &[String], id: usize, section: &Section, cancelled: &mut watch::Receiver<bool>) -> anyhow::Result<Outcome> {
+    let shown = !remote.silent && (section.verbose || !section.dependency);
+    let output = |message: String| then!(shown, job.emit(Response::message(Level::None, message)));
+
+    let mut code = Some(0);
+    for (index, command) in section.lines.iter().enumerate() {
+        let step = Step {
+            task: section.task.clone(),
+            index: index + 1,
+            total: section.lines.len(),
+            command: command.clone(),
+            phase: Phase::Start,
+            duration: None,
+        };
+
+        job.emit(Response::step(step.clone(), None));
+        let path = script::path(id, index + 1);
+        let start = Instant::now();
+
+        let exited = tokio::select! {
+            exited = backend.run(workspace, &section.dir, &remote.shell, &path, env, &output) => exited?,
+            _ = cancellation(cancelled) => return Ok(Outcome::Cancelled),
+        };
+
+        let duration = Some(start.elapsed().as_millis() as u64);
+        job.emit(Response::step(Step { phase: Phase::End, duration, ..step }, exited));
+
+        if exited != Some(0) {
+            then!(code == Some(0), code = exited);
+            then!(!section.continue_on_error, break);
+        }
+    }
+
+    Ok(Outcome::Exited { code, artifact: None })
+}
+
+// resolves once the job is cancelled
+async fn cancellation(cancelled: &mut watch::Receiver<bool>) {
+    loop {
+        then!(*cancelled.borrow_and_update(), return);
+
+        if cancelled.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::bail;
+use maid::{graph::Graph, models::shared::Maidfile};
+use serde_json::Value;
+use text_placeholder::Template;
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Component, Path},
+};
+
+use tar::{Builder, EntryType, Header};
+
+// step scripts are kept apart from the pushed files, relative to the workdir
+const DIR: &str = ".maid/steps";
+
+/// A task of the dependency graph as it runs on the server, dependencies first and the task itself last.
+pub struct Section {
+    pub task: String,
+    /// Where its lines run, relative to the workdir.
+    pub dir: String,
+    pub lines: Vec<String>,
+    pub dependency: bool,
+    /// Set for `log:` dependencies, the output of other dependencies is not shown.
+    pub verbose: bool,
+    pub continue_on_error: bool,
+}
+
+/// Where the script of a step ends up, relative to the workdir.
+pub fn path(section: usize, index: usize) -> String { format!("{DIR}/{section:02}-{index:03}.sh") }
+
+fn lines(task: &str, script: &Value) -> anyhow::Result<Vec<String>> {
+    match script {
+        Value::String(line) => Ok(vec![line.clone()]),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item.as_str() {
+                Some(line) => Ok(line.to_string()),
+                None => bail!("script of task '{task}' must only hold strings"),
+            })
+            .collect(),
+        _ => bail!("script of task '{task}' must be a string or an array of strings"),
+    }
+}
+
+// like a local run the path is relative to the project, which is the workdir here
+fn dir(task: &str, path: Option<&str>) -> anyhow::Result<String> {
+    let path = match path {
+        None | Some("") | Some("%{dir.current}") => return Ok(String::new()),
+        Some(path) => path,
+    };
+
+    if !Path::new(path).components().all(|item| matches!(item, Component::Normal(_) | Component::CurDir)) {
+        bail!("path '{path}' of task '{task}' must be relative to the project to run remotely");
+    }
+
+    Ok(path.to_string())
+}
+
+/// Resolves the dependency graph of `root` the way a local run does.
+pub fn plan(maidfile: &Maidfile<Value>, root: &str) -> anyhow::Result<Vec<Section>> {
+    if !maidfile.tasks.contains_key(root) {
+        bail!("task '{root}' is not in the Maidfile");
+    }
+
+    let graph = Graph::new(maidfile, root)?;
+    let last = graph.nodes().len() - 1;
+
+    graph
+        .nodes()
+        .iter()
+        .enumerate()
+        .map(|(id, node)| {
+            let task = &maidfile.tasks[&node.name];
+
+            Ok(Section {
+                task: node.name.clone(),
+                dir: dir(&node.name, task.path.as_deref())?,
+                lines: lines(&node.name, &task.script)?,
+                dependency: id != last,
+                verbose: node.verbose,
+                continue_on_error: task.continue_on_error.unwrap_or(false),
+            })
+        })
+        .collect()
+}
+
+fn directory(tar: &mut Builder<Vec<u8>>, path: &str) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+
+    header.set_entry_type(EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_size(0);
+    header.set_cksum();
+    tar.append_data(&mut header, path, io::empty())
+}
+
+/// Adds every line to the tree as a script of its own, so no line is ever pasted into a shell command.
+pub fn append(tar: &mut Builder<Vec<u8>>, sections: &[Section], table: &HashMap<&str, &str>) -> anyhow::Result<()> {
+    directory(tar, ".maid")?;
+    directory(tar, DIR)?;
+
+    for (section, item) in sections.iter().enumerate() {
+        for (index, line) in item.lines.iter().enumerate() {
+            let body = format!("set -e\n{}\n", Template::new_with_placeholder(line, "%{", "}").fill_with_hashmap(table));
+            let mut header = Header::new_gnu();
+
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(0o755);
+            header.set_size(body.len() as u64);
+            header.set_cksum();
+            tar.append_data(&mut header, path(section, index + 1), body.as_bytes())?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use crate::{
+    audit,
+    auth::Token,
+    backend::ExecutionBackend,
+    config::Config,
+    jobs::{Event, Job, Jobs},
+    queue::Queue,
+    runner, settings, store, transfer, IntoMessage,
+};
+
+use anyhow::{anyhow, bail};
+use macros_rs::exp::{then, ternary};
+use maid::{
+    log::prelude::*,
+    protocol::{ConnectionData, Kind, Level, Manifest, Missing, Request, Response},
+};
+
+use rocket::futures::{SinkExt, StreamExt};
+use rocket_ws::{stream::DuplexStream, Message};
+use serde_json::Value;
+use std::sync::Arc;
+use tar::Builder;
+use tokio::sync::broadcast::error::RecvError;
+
+pub async fn handle(mut stream: DuplexStream, backend: &Arc<dyn ExecutionBackend>, settings: &Config, queue: &Queue, jobs: &Jobs, token: &Token) -> anyhow::Result<()> {
+    let request = loop {
+        match stream.next().await {
+            Some(Ok(Message::Text(text))) => match serde_json::from_str::<Request<Value>>(&text) {
+                Ok(request) => break request,
+                Err(err) => bail!("Failed to deserialize JSON: {err}"),
+            },
+            Some(Ok(_)) => continue,
+            Some(Err(err)) => return Err(err.into()),
+            None => bail!("client disconnected before sending a request"),
+        }
+    };
+
+    match request {
+        Request::Run(parsed) => run(stream, backend, settings, queue, jobs, token, *parsed).await,
+        Request::Attach { attach, follow } => match jobs.get(&attach, token) {
+            Some(job) => {
+                audit::record(
+                    settings,
+                    audit::Event {
+                        token: &token.name,
+                        action: ternary!(follow, "attach", "logs"),
+                        detail: Some(&job.id),
+                        ..Default::default()
+                    },
+                );
+                self::attach(&mut stream, &job, follow, false).await
+            }
+            None => refuse(stream, format!("no job {attach} on this server")).await,
+        },
+        Request::Fetch { fetch } => self::fetch(stream, settings, jobs, token, &fetch).await,
+    }
+}
+
+async fn refuse(mut stream: DuplexStream, reason: String) -> anyhow::Result<()> {
+    stream.send(Response::message(Level::Fatal, &reason).into_message()).await?;
+    stream.send(Response::done(None).into_message()).await?;
+
+    Err(anyhow!(reason))
+}
+
+async fn run(mut stream: DuplexStream, backend: &Arc<dyn ExecutionBackend>, settings: &Config, queue: &Queue, jobs: &Jobs, token: &Token, parsed: ConnectionData<Value>) -> anyhow::Result<()> {
+    let name = &parsed.info.name;
+    let image = parsed.info.remote.image.clone();
+
+    if !token.can_run(&image) {
+        audit::record(
+            settings,
+            audit::Event {
+                token: &token.name,
+                action: "denied",
+                task: Some(name),
+                image: Some(&image),
+                detail: Some("token may not run this image"),
+                ..Default::default()
+            },
+        );
+
+        return refuse(stream, format!("token '{}' is not allowed to run image {image}", token.name)).await;
+    }
+
+    let options = match settings::resolve(&parsed.info.remote, &settings.containers) {
+        Ok(options) => options,
+        Err(err) => {
+            let reason = format!("{err:#}");
+
+            audit::record(
+                settings,
+                audit::Event {
+                    token: &token.name,
+                    action: "denied",
+                    task: Some(name),
+                    image: Some(&image),
+                    detail: Some(&reason),
+                    ..Default::default()
+                },
+            );
+
+            return refuse(stream, reason).await;
+        }
+    };
+
+    stream.send(Response::new(Level::Success, Kind::Binary, None).into_message()).await?;
+
+    let tree = match push(&mut stream, settings).await {
+        Ok(tree) => tree,
+        Err(err) => {
+            let _ = stream.send(Response::message(Level::Fatal, format!("{err:#}")).into_message()).await;
+            return Err(err);
+        }
+    };
+
+    // projects without a name only lock against runs from the same token
+    let project = parsed.maidfile.project.as_ref().and_then(|project| project.name.clone()).unwrap_or_else(|| token.name.clone());
+    let job = match jobs.create(name, &project, token, &image) {
+        Ok(job) => job,
+        Err(err) => return refuse(stream, format!("{err:#}")).await,
+    };
+
+    info!("queued job {} (task={name}, image={image})", job.id);
+
+    let detach = parsed.info.detach;
+    let run = runner::run(Arc::clone(&job), Arc::clone(backend), settings.clone(), queue.clone(), token.clone(), parsed, options, tree);
+    let jobs = jobs.clone();
+
+    // every finished run is a chance to let go of the ones that expired since startup
+    tokio::spawn(async move {
+        run.await;
+        let _ = tokio::task::spawn_blocking(move || jobs.prune()).await;
+    });
+
+    stream.send(Response::new(Level::Success, Kind::Job, Some(job.id.clone())).into_message()).await?;
+
+    match detach {
+        true => Ok(()),
+        false => attach(&mut stream, &job, true, true).await,
+    }
+}
+
+// the client sends a manifest, uploads the objects the store lacks, and the tree is rebuilt from the store
+async fn push(stream: &mut DuplexStream, settings: &Config) -> anyhow::Result<Builder<Vec<u8>>> {
+    let manifest = match transfer::next(stream).await? {
+        Message::Text(text) => serde_json::from_str::<Manifest>(&text)?,
+        _ => bail!("expected a push manifest"),
+    };
+
+    let dir = &settings.server.store;
+    let missing = store::missing(dir, &manifest.entries, &settings.server.limits())?;
+
+    info!("manifest lists {} entries, {} objects missing", manifest.entries.len(), missing.len());
+    stream.send(Message::text(serde_json::to_string(&Missing { missing: missing.clone() })?)).await?;
+
+    if !missing.is_empty() {
+        let bundle = transfer::receive(stream, &settings.server.transfers, settings.server.limits().bytes).await?;
+        let stored = store::unpack(dir, &bundle, &missing);
+
+        let _ = std::fs::remove_file(&bundle);
+        info!("stored {} new objects", stored?);
+    }
+
+    store::tree(dir, &manifest.entries)
+}
+
+/// Replays what a job logged so far, then follows it live until it ends and hands over its artifact.
+/// Without `follow` only the log so far is sent, along with the job's status.
+async fn attach(stream: &mut DuplexStream, job: &Job, follow: bool, owner: bool) -> anyhow::Result<()> {
+    let result = replay(stream, job, follow).await;
+
+    // the client that started an attached run takes the job with it when it leaves
+    if result.is_err() && owner && !job.summary().status.finished() {
+        warn!("client disconnected, cancelling job {}", job.id);
+        job.cancel();
+    }
+
+    result
+}
+
+async fn replay(stream: &mut DuplexStream, job: &Job, follow: bool) -> anyhow::Result<()> {
+    let mut seen = 0;
+
+    loop {
+        let (mut events, lines, finished) = job.subscribe();
+
+        for line in job.replay(seen, lines).await? {
+            stream.send(Message::text(line)).await?;
+        }
+        seen = lines;
+
+        if finished || !follow {
+            break;
+        }
+
+        // a slow client that fell behind the live events catches up from the log again
+        let lagged = loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(Event::Line(seq, line)) if seq >= seen => {
+                        stream.send(Message::text(line)).await?;
+                        seen = seq + 1;
+                    }
+                    Ok(Event::Line(..)) => {}
+                    Ok(Event::Finished) | Err(RecvError::Closed) => break false,
+                    Err(RecvError::Lagged(_)) => break true,
+                },
+                frame = stream.next() => match frame {
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => bail!("client disconnected from job {}", job.id),
+                    Some(Ok(_)) => {}
+                },
+            }
+        };
+
+        then!(!lagged, break);
+    }
+
+    let summary = job.summary();
+
+    if !follow {
+        // the job may still be running, the request itself went fine
+        stream.send(Response::message(Level::Info, format!("job {} is {}", job.id, summary.status)).into_message()).await?;
+        stream.send(Response::done(Some(0)).into_message()).await?;
+        return Ok(());
+    }
+
+    // failed jobs only keep an artifact when their task pulls on failure
+    if let Some(artifact) = &summary.artifact {
+        transfer::send(stream, &job.artifact_path(), artifact).await?;
+        info!("sent artifact {artifact} of job {}", job.id);
+    }
+
+    stream.send(Response::done(summary.code).into_message()).await?;
+    info!("sent message: [done]");
+
+    Ok(())
+}
+
+// a client that lost its connection during a pull asks for the artifact again by its hash
+async fn fetch(mut stream: DuplexStream, settings: &Config, jobs: &Jobs, token: &Token, artifact: &str) -> anyhow::Result<()> {
+    audit::record(
+        settings,
+        audit::Event {
+            token: &token.name,
+            action: "fetch",
+            detail: Some(artifact),
+            ..Default::default()
+        },
+    );
+
+    let job = match jobs.artifact(artifact, token).filter(|job| job.artifact_path().is_file()) {
+        Some(job) => job,
+        None => return refuse(stream, format!("artifact {artifact} is no longer kept on the server")).await,
+    };
+
+    transfer::send(&mut stream, &job.artifact_path(), artifact).await?;
+    info!("sent artifact {artifact} again after a dropped connection");
+
+    stream.send(Response::done(job.summary().code).into_message()).await?;
+
+    Ok(())
+}
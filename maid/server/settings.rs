@@ -0,0 +1,226 @@
+use crate::config::Containers;
+
+use anyhow::bail;
+use maid::models::shared::Remote;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Component, Path},
+};
+
+// pushed files land here and the script runs here, unless the task picks another directory
+pub const WORKDIR: &str = "/opt";
+
+/// Container settings of a task, once they passed the server's allowlist.
+#[derive(Clone, Debug)]
+pub struct RunSettings {
+    pub nano_cpus: Option<i64>,
+    pub memory: Option<i64>,
+    pub network: String,
+    pub binds: Vec<String>,
+    pub workdir: String,
+    pub user: Option<String>,
+}
+
+/// Docker style sizes, a whole number of bytes with an optional k, m or g suffix.
+pub fn bytes(value: &str) -> Option<i64> {
+    let value = value.trim().to_lowercase();
+    let split = value.find(|item: char| item.is_ascii_alphabetic()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let scale: i64 = match unit {
+        "" | "b" => 1,
+        "k" | "kb" => 1 << 10,
+        "m" | "mb" => 1 << 20,
+        "g" | "gb" => 1 << 30,
+        _ => return None,
+    };
+
+    number.trim().parse::<i64>().ok()?.checked_mul(scale).filter(|bytes| *bytes > 0)
+}
+
+// an absolute path below the root without any `..`
+fn absolute(path: &str) -> bool {
+    let path = Path::new(path);
+    path.is_absolute() && path.components().count() > 1 && path.components().skip(1).all(|item| matches!(item, Component::Normal(_)))
+}
+
+pub fn resolve(remote: &Remote, allow: &Containers) -> anyhow::Result<RunSettings> {
+    // without a request of their own, tasks get the most the server allows
+    let cpus = match (remote.cpus, allow.max_cpus) {
+        (Some(cpus), _) if cpus.is_nan() || cpus <= 0.0 => bail!("cpus must be above 0"),
+        (Some(cpus), Some(max)) if cpus > max => bail!("cpus {cpus} is above the server limit of {max}"),
+        (cpus, max) => cpus.or(max),
+    };
+
+    let max_memory = allow.max_memory.as_deref().and_then(bytes);
+    let memory = match remote.memory.as_deref() {
+        Some(memory) => match bytes(memory) {
+            None => bail!("memory '{memory}' is not a size like 512m or 4g"),
+            Some(value) if max_memory.is_some_and(|max| value > max) => bail!("memory {memory} is above the server limit of {}", allow.max_memory.as_deref().unwrap_or_default()),
+            Some(value) => Some(value),
+        },
+        None => max_memory,
+    };
+
+    let network = remote.network.clone().unwrap_or_else(|| allow.network.clone());
+    if !allow.networks.contains(&network) {
+        bail!("network '{network}' is not allowed on this server, use one of: {}", allow.networks.join(", "));
+    }
+
+    let workdir = remote.workdir.clone().unwrap_or_else(|| WORKDIR.to_string());
+    if !absolute(&workdir) {
+        bail!("workdir '{workdir}' must be an absolute path below /");
+    }
+
+    let binds = remote.volumes.iter().map(|volume| bind(volume, allow)).collect::<anyhow::Result<Vec<_>>>()?;
+
+    if let Some(user) = &remote.user {
+        if !allow.users.contains(user) {
+            bail!("user '{user}' is not allowed on this server");
+        }
+    }
+
+    Ok(RunSettings {
+        nano_cpus: cpus.map(|cpus| (cpus * 1e9) as i64),
+        memory,
+        network,
+        binds,
+        workdir,
+        user: remote.user.clone(),
+    })
+}
+
+// volumes are source:/path with an optional :ro or :rw, only allowlisted sources are mounted
+fn bind(volume: &str, allow: &Containers) -> anyhow::Result<String> {
+    let parts: Vec<&str> = volume.split(':').collect();
+
+    let (source, target, mode) = match parts.as_slice() {
+        [source, target] => (*source, *target, "rw"),
+        [source, target, mode @ ("ro" | "rw")] => (*source, *target, *mode),
+        _ => bail!("volume '{volume}' must look like source:/path or source:/path:ro"),
+    };
+
+    if !allow.volumes.iter().any(|allowed| allowed == source) {
+        bail!("volume '{source}' is not allowed on this server");
+    }
+
+    if !absolute(target) {
+        bail!("volume target '{target}' must be an absolute path below /");
+    }
+
+    Ok(format!("{source}:{target}:{mode}"))
+}
+
+/// The Maidfile's `[env]` as rendered for the script, handed to the container as its environment.
+pub fn environment<T>(env: Option<&BTreeMap<String, T>>, table: &HashMap<&str, &str>) -> anyhow::Result<Vec<String>> {
+    let mut vars = vec![];
+
+    for key in env.into_iter().flat_map(|env| env.keys()) {
+        let valid = key.chars().next().is_some_and(|item| item.is_ascii_alphabetic() || item == '_') && key.chars().all(|item| item.is_ascii_alphanumeric() || item == '_');
+
+        if !valid {
+            bail!("env '{key}' is not a valid variable name");
+        }
+
+        // the table escapes quotes for the script, the container gets the value as written
+        let value = table.get(format!("env.{key}").as_str()).copied().unwrap_or_default();
+        vars.push(format!("{key}={}", value.replace("\\\"", "\"")));
+    }
+
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(extra: &str) -> Remote { toml::from_str(&format!("push = []\nimage = \"alpine\"\nshell = \"sh\"\nsilent = false\nexclusive = false\n{extra}")).unwrap() }
+
+    fn allow() -> Containers {
+        Containers {
+            max_cpus: Some(2.0),
+            max_memory: Some("1g".to_string()),
+            volumes: vec!["cache".to_string()],
+            users: vec!["builder".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn refused(extra: &str) -> String {
+        match resolve(&remote(extra), &allow()) {
+            Ok(settings) => panic!("expected a refusal, got {settings:?}"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn sizes_parse_like_docker() {
+        assert_eq!(bytes("512"), Some(512));
+        assert_eq!(bytes("4k"), Some(4 << 10));
+        assert_eq!(bytes(" 512M "), Some(512 << 20));
+        assert_eq!(bytes("2gb"), Some(2 << 30));
+
+        for value in ["", "0", "-1m", "1t", "m", "1.5g"] {
+            assert_eq!(bytes(value), None, "{value} should not parse");
+        }
+    }
+
+    #[test]
+    fn accepts_requests_within_the_limits() {
+        let settings = resolve(&remote("cpus = 1.5\nmemory = \"512m\"\nnetwork = \"none\"\nvolumes = [\"cache:/cache:ro\"]\nworkdir = \"/src/app\"\nuser = \"builder\""), &allow()).unwrap();
+
+        assert_eq!(settings.nano_cpus, Some(1_500_000_000));
+        assert_eq!(settings.memory, Some(512 << 20));
+        assert_eq!(settings.network, "none");
+        assert_eq!(settings.binds, ["cache:/cache:ro"]);
+        assert_eq!(settings.workdir, "/src/app");
+        assert_eq!(settings.user.as_deref(), Some("builder"));
+    }
+
+    #[test]
+    fn defaults_to_the_server_limits() {
+        let settings = resolve(&remote(""), &allow()).unwrap();
+
+        assert_eq!(settings.nano_cpus, Some(2_000_000_000));
+        assert_eq!(settings.memory, Some(1 << 30));
+        assert_eq!(settings.network, "bridge");
+        assert!(settings.binds.is_empty());
+        assert_eq!(settings.workdir, WORKDIR);
+        assert_eq!(settings.user, None);
+    }
+
+    #[test]
+    fn refuses_cpus_over_the_limit() {
+        assert!(refused("cpus = 4.0").contains("above the server limit of 2"));
+        assert!(refused("cpus = 0.0").contains("must be above 0"));
+        assert!(refused("cpus = -1.0").contains("must be above 0"));
+    }
+
+    #[test]
+    fn refuses_memory_over_the_limit() {
+        assert!(refused("memory = \"2g\"").contains("above the server limit of 1g"));
+        assert!(refused("memory = \"lots\"").contains("is not a size"));
+    }
+
+    #[test]
+    fn refuses_networks_that_are_not_allowed() { assert!(refused("network = \"host\"").contains("network 'host' is not allowed")) }
+
+    #[test]
+    fn refuses_volumes_that_are_not_allowed() {
+        assert!(refused("volumes = [\"/var/run/docker.sock:/sock\"]").contains("is not allowed"));
+        assert!(refused("volumes = [\"cache:/cache:rx\"]").contains("must look like"));
+        assert!(refused("volumes = [\"cache\"]").contains("must look like"));
+        assert!(refused("volumes = [\"cache:relative\"]").contains("volume target 'relative'"));
+        assert!(refused("volumes = [\"cache:/cache/../etc\"]").contains("volume target"));
+    }
+
+    #[test]
+    fn refuses_users_that_are_not_allowed() { assert!(refused("user = \"root\"").contains("user 'root' is not allowed")) }
+
+    #[test]
+    fn refuses_workdirs_outside_the_container_root() {
+        for workdir in ["/", "relative", "/opt/../etc", "./opt", ""] {
+            assert!(refused(&format!("workdir = \"{workdir}\"")).contains("must be an absolute path below /"), "{workdir} should be refused");
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{bail, Context};
+use flate2::read::GzDecoder;
+use maid::{
+    archive::Limits,
+    log::prelude::*,
+    models::client::{CacheEntry, EntryKind},
+    transfer::valid_id,
+};
+
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use tar::{Archive, Builder, EntryType, Header};
+use uuid::Uuid;
+
+fn object(dir: &Path, hash: &str) -> PathBuf { dir.join(hash) }
+
+// manifest paths come from the client and become paths under /opt in the container
+fn relative(path: &str) -> bool {
+    let path = Path::new(path);
+    !path.as_os_str().is_empty() && path.components().all(|item| matches!(item, Component::Normal(_) | Component::CurDir))
+}
+
+fn hash(entry: &CacheEntry) -> anyhow::Result<&str> {
+    match entry.hash.as_deref() {
+        Some(hash) if valid_id(hash) => Ok(hash),
+        _ => bail!("manifest entry '{}' has no valid hash", entry.path),
+    }
+}
+
+/// Hashes of files in the manifest that are not in the content store yet.
+pub fn missing(dir: &Path, entries: &[CacheEntry], limits: &Limits) -> anyhow::Result<Vec<String>> {
+    let mut missing = BTreeSet::new();
+
+    // refused before anything is uploaded, the tree itself is checked again before it is unpacked
+    if entries.len() > limits.entries {
+        bail!("push holds {} entries, more than the server limit of {}", entries.len(), limits.entries);
+    }
+
+    let size = entries.iter().fold(0u64, |size, entry| size.saturating_add(entry.size));
+    if size > limits.bytes {
+        bail!("push is {size} bytes, more than the server limit of {}", limits.bytes);
+    }
+
+    for entry in entries {
+        if !relative(&entry.path) {
+            bail!("manifest entry '{}' must be a relative path inside the project", entry.path);
+        }
+
+        if entry.kind == EntryKind::File {
+            let hash = hash(entry)?;
+            if !object(dir, hash).is_file() {
+                missing.insert(hash.to_string());
+            }
+        }
+    }
+
+    Ok(missing.into_iter().collect())
+}
+
+fn insert(dir: &Path, hash: &str, reader: &mut impl Read) -> anyhow::Result<()> {
+    let temp = dir.join(format!("{hash}.tmp-{}", Uuid::new_v4()));
+    let mut hasher = blake3::Hasher::new();
+    let mut file = File::create(&temp)?;
+    let mut buffer = [0u8; 65536];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+    }
+
+    if hasher.finalize().to_hex().as_str() != hash {
+        fs::remove_file(&temp)?;
+        bail!("object {hash} does not match its contents");
+    }
+
+    fs::rename(&temp, object(dir, hash))?;
+    Ok(())
+}
+
+/// Moves the objects of an uploaded bundle into the store, anything that was not asked for is skipped.
+pub fn unpack(dir: &Path, bundle: &Path, wanted: &[String]) -> anyhow::Result<usize> {
+    let mut archive = Archive::new(GzDecoder::new(File::open(bundle)?));
+    let mut stored = 0;
+
+    fs::create_dir_all(dir)?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+
+        if !valid_id(&name) || !wanted.contains(&name) {
+            warn!("skipping unexpected object '{name}' in upload");
+            continue;
+        }
+
+        insert(dir, &name, &mut entry)?;
+        stored += 1;
+    }
+
+    Ok(stored)
+}
+
+/// Rebuilds the pushed tree as a tar for the workspace, left open so the task's scripts can be added.
+pub fn tree(dir: &Path, entries: &[CacheEntry]) -> anyhow::Result<Builder<Vec<u8>>> {
+    let mut tar = Builder::new(Vec::new());
+
+    for entry in entries {
+        let mut header = Header::new_gnu();
+
+        match entry.kind {
+            EntryKind::Directory => {
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(entry.mode & 0o7777);
+                header.set_size(0);
+                header.set_cksum();
+                tar.append_data(&mut header, &entry.path, io::empty())?;
+            }
+            EntryKind::File => {
+                let path = object(dir, hash(entry)?);
+                let file = File::open(&path).with_context(|| format!("object for '{}' is missing from the store", entry.path))?;
+
+                header.set_entry_type(EntryType::Regular);
+                header.set_mode(entry.mode & 0o7777);
+                header.set_size(file.metadata()?.len());
+                header.set_cksum();
+                tar.append_data(&mut header, &entry.path, file)?;
+            }
+            EntryKind::Symlink => {
+                header.set_entry_type(EntryType::Symlink);
+                header.set_mode(0o777);
+                header.set_size(0);
+                tar.append_link(&mut header, &entry.path, entry.link.as_deref().unwrap_or_default())?;
+            }
+        }
+    }
+
+    debug!("rebuilt tree with {} entries", entries.len());
+    Ok(tar)
+}
@@ -0,0 +1,133 @@
+use anyhow::{anyhow, bail};
+use maid::{
+    log::prelude::*,
+    protocol::Transfer,
+    transfer::{chunks, Chunk, Partial},
+};
+
+use rocket::futures::{SinkExt, StreamExt};
+use rocket_ws::{stream::DuplexStream, Message};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+// resend rounds before a transfer that keeps failing its checksums is given up
+const ROUNDS: usize = 5;
+
+// staged artifacts nobody came back for within a day are removed on startup
+const STALE: Duration = Duration::from_secs(60 * 60 * 24);
+
+pub async fn next(stream: &mut DuplexStream) -> anyhow::Result<Message> {
+    loop {
+        match stream.next().await {
+            Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+            Some(Ok(Message::Close(_))) | None => bail!("connection closed during transfer"),
+            Some(Ok(message)) => return Ok(message),
+            Some(Err(err)) => return Err(err.into()),
+        }
+    }
+}
+
+async fn control(stream: &mut DuplexStream) -> anyhow::Result<Transfer> {
+    match next(stream).await? {
+        Message::Text(text) => serde_json::from_str(&text).map_err(|err| anyhow!("expected a transfer frame: {err}")),
+        _ => bail!("expected a transfer frame, got binary data"),
+    }
+}
+
+/// Accepts an artifact of at most `max` bytes, a larger offer is refused before anything is staged.
+pub async fn receive(stream: &mut DuplexStream, dir: &Path, max: u64) -> anyhow::Result<PathBuf> {
+    let (id, size) = match control(stream).await? {
+        Transfer::Offer { id, size } => (id, size),
+        frame => bail!("expected a transfer offer, got {frame:?}"),
+    };
+
+    if size > max {
+        bail!("transfer {id} is {size} bytes, more than the server limit of {max}");
+    }
+
+    let mut partial = Partial::open(dir, &id, size)?;
+    let mut rounds = 0;
+
+    if partial.next() > 0 {
+        info!("resuming transfer {id} at chunk {}", partial.next());
+    }
+
+    loop {
+        if partial.done() {
+            match partial.finish()? {
+                Some(path) => {
+                    stream.send(Message::text(Transfer::Complete { id }.to_json())).await?;
+                    return Ok(path);
+                }
+                None => {
+                    warn!("transfer {id} does not match its checksum, starting over");
+                    partial = Partial::open(dir, &id, size)?;
+                }
+            }
+        }
+
+        rounds += 1;
+        if rounds > ROUNDS {
+            bail!("transfer {id} is still incomplete after {ROUNDS} rounds");
+        }
+
+        let from = partial.next();
+        stream.send(Message::text(Transfer::Resume { id: id.clone(), from }.to_json())).await?;
+
+        for _ in from..chunks(size) {
+            let chunk = match next(stream).await? {
+                Message::Binary(frame) => Chunk::decode(&frame).ok_or_else(|| anyhow!("malformed chunk in transfer {id}"))?,
+                _ => bail!("expected a chunk of transfer {id}"),
+            };
+
+            if !partial.write(&chunk)? {
+                debug!("dropped chunk {} of transfer {id}", chunk.index);
+            }
+        }
+    }
+}
+
+pub async fn send(stream: &mut DuplexStream, path: &Path, id: &str) -> anyhow::Result<()> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    stream.send(Message::text(Transfer::Offer { id: id.to_string(), size }.to_json())).await?;
+
+    for _ in 0..=ROUNDS {
+        match control(stream).await? {
+            Transfer::Complete { .. } => return Ok(()),
+            Transfer::Resume { from, .. } => {
+                if from > 0 {
+                    info!("resuming transfer {id} at chunk {from}");
+                }
+                for index in from..chunks(size) {
+                    stream.send(Message::binary(Chunk::read(&mut file, index)?)).await?;
+                }
+            }
+            Transfer::Offer { .. } => bail!("unexpected transfer offer while sending {id}"),
+        }
+    }
+
+    bail!("transfer {id} was not accepted after {ROUNDS} rounds")
+}
+
+pub fn prune(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let modified = entry.metadata().and_then(|meta| meta.modified()).unwrap_or(SystemTime::now());
+
+        if modified.elapsed().is_ok_and(|age| age > STALE) {
+            match fs::remove_file(entry.path()) {
+                Ok(_) => debug!("removed stale transfer {}", entry.path().display()),
+                Err(err) => warn!(%err, "unable to remove stale transfer {}", entry.path().display()),
+            }
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::{
+    collections::HashSet,
+    fmt, fs, io,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use tar::{Archive, EntryType};
+
+// the error lists this many offending entries before it only counts the rest
+const LISTED: usize = 20;
+
+/// Caps an archive has to stay under before anything is unpacked.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            entries: 100_000,
+            bytes: 4 << 30,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(io::Error),
+    /// Every entry that may not be unpacked, with the reason.
+    Rejected(Vec<(String, String)>),
+    TooManyEntries(usize),
+    TooLarge(u64),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Io(err) => write!(f, "{err}"),
+            ArchiveError::TooManyEntries(limit) => write!(f, "archive holds more than {limit} entries"),
+            ArchiveError::TooLarge(limit) => write!(f, "archive unpacks to more than {limit} bytes"),
+            ArchiveError::Rejected(entries) => {
+                write!(f, "archive holds {} unsafe {}, nothing was unpacked:", entries.len(), if entries.len() > 1 { "entries" } else { "entry" })?;
+
+                for (path, reason) in entries.iter().take(LISTED) {
+                    write!(f, "\n  - {path} ({reason})")?;
+                }
+
+                match entries.len() > LISTED {
+                    true => write!(f, "\n  ...and {} more", entries.len() - LISTED),
+                    false => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArchiveError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(err: io::Error) -> Self { ArchiveError::Io(err) }
+}
+
+/// Picks the destination of an entry, as an index into the roots and the path below it, or why it has none.
+pub type Route<'a> = dyn Fn(&Path) -> Result<(usize, PathBuf), String> + 'a;
+
+fn plain(path: &Path) -> bool { path.components().all(|item| matches!(item, Component::Normal(_) | Component::CurDir)) }
+
+// where a relative symlink lands, none when it climbs out of its root
+fn resolve(entry: &Path, link: &Path) -> Option<PathBuf> {
+    let mut resolved: Vec<Component> = entry.parent().map(|parent| parent.components().collect()).unwrap_or_default();
+
+    for component in link.components() {
+        match component {
+            Component::Normal(_) => resolved.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(resolved.iter().collect())
+}
+
+// rules for the name as stored in the archive
+fn name(path: &Path) -> Result<(), String> {
+    match (path.has_root(), plain(path)) {
+        (true, _) => Err(String::from("absolute path")),
+        (false, false) => Err(String::from("climbs out with '..'")),
+        (false, true) => Ok(()),
+    }
+}
+
+// rules for the entry once it has a place below its root
+fn check(root: usize, relative: &Path, kind: EntryType, link: Option<&Path>, links: &HashSet<(usize, PathBuf)>) -> Result<(), String> {
+    if let Some(parent) = relative.ancestors().skip(1).find(|item| links.contains(&(root, item.to_path_buf()))) {
+        return Err(format!("below the symlink '{}'", parent.display()));
+    }
+
+    match kind {
+        EntryType::Regular | EntryType::Continuous | EntryType::Directory => Ok(()),
+        EntryType::Symlink => match link {
+            None => Err(String::from("symlink without a target")),
+            Some(link) if link.has_root() => Err(format!("symlink to absolute path '{}'", link.display())),
+            Some(link) if resolve(relative, link).is_none() => Err(format!("symlink to '{}' escapes its destination", link.display())),
+            Some(_) => Ok(()),
+        },
+        EntryType::Link => Err(String::from("hard links are not supported")),
+        _ => Err(String::from("not a file, directory or symlink")),
+    }
+}
+
+/// Applies the rules of `verify` to an entry that does not come from a tar, like one of a cache manifest. `links` keeps the symlinks seen so far.
+pub fn entry(path: &Path, kind: EntryType, link: Option<&Path>, links: &mut HashSet<(usize, PathBuf)>) -> Result<(), String> {
+    name(path)?;
+    check(0, path, kind, link, links)?;
+
+    if kind == EntryType::Symlink {
+        links.insert((0, path.to_path_buf()));
+    }
+
+    Ok(())
+}
+
+/// Checks every entry before anything is written and returns the bytes bound for each root, none for roots without entries.
+pub fn verify<R: Read>(reader: R, roots: &[PathBuf], limits: &Limits, route: &Route) -> Result<Vec<Option<u64>>, ArchiveError> {
+    let mut archive = Archive::new(reader);
+    let mut sizes: Vec<Option<u64>> = vec![None; roots.len()];
+    let mut rejected = vec![];
+    let mut links = HashSet::new();
+    let mut total: u64 = 0;
+
+    for (count, entry) in archive.entries()?.enumerate() {
+        if count >= limits.entries {
+            return Err(ArchiveError::TooManyEntries(limits.entries));
+        }
+
+        let entry = entry?;
+        let path = entry.path()?.into_owned();
+        let kind = entry.header().entry_type();
+        let link = entry.link_name()?.map(|link| link.into_owned());
+
+        total = total.saturating_add(entry.size());
+        if total > limits.bytes {
+            return Err(ArchiveError::TooLarge(limits.bytes));
+        }
+
+        let (root, relative) = match name(&path).and_then(|_| route(&path)) {
+            Ok((root, relative)) if root < roots.len() => (root, relative),
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "route picked an unknown root").into()),
+            Err(reason) => {
+                rejected.push((path.display().to_string(), reason));
+                continue;
+            }
+        };
+
+        // offending entries are named by where they would have landed
+        if let Err(reason) = check(root, &relative, kind, link.as_deref(), &links) {
+            rejected.push((roots[root].join(&relative).display().to_string(), reason));
+            continue;
+        }
+
+        if kind == EntryType::Symlink {
+            links.insert((root, relative));
+        }
+
+        *sizes[root].get_or_insert(0) += entry.size();
+    }
+
+    match rejected.is_empty() {
+        true => Ok(sizes),
+        false => Err(ArchiveError::Rejected(rejected)),
+    }
+}
+
+/// Verifies an archive, then unpacks every entry below its root. `open` is called once for each pass.
+pub fn extract<R: Read>(open: impl Fn() -> io::Result<R>, roots: &[PathBuf], limits: &Limits, route: &Route) -> Result<Vec<Option<u64>>, ArchiveError> {
+    let sizes = verify(open()?, roots, limits, route)?;
+    let mut archive = Archive::new(open()?);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let (root, relative) = route(&path).map_err(|reason| ArchiveError::Rejected(vec![(path.display().to_string(), reason)]))?;
+
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+
+        fs::create_dir_all(&roots[root])?;
+        let base = fs::canonicalize(&roots[root])?;
+        let target = base.join(&relative);
+
+        // symlinks already on disk may not carry an entry out of its root either
+        let existing = target.ancestors().skip(1).find(|item| item.exists()).unwrap_or(&base);
+        if !fs::canonicalize(existing)?.starts_with(&base) {
+            return Err(ArchiveError::Rejected(vec![(path.display().to_string(), String::from("lands outside its destination"))]));
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        entry.unpack(&target)?;
+    }
+
+    Ok(sizes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tar::{Builder, Header};
+
+    enum Item<'a> {
+        File(&'a str, &'a [u8]),
+        Dir(&'a str),
+        Symlink(&'a str, &'a str),
+        HardLink(&'a str, &'a str),
+    }
+
+    // names are written into the header as they are, the tar crate would refuse the unsafe ones
+    fn tar(items: &[Item]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        for item in items {
+            let mut header = Header::new_gnu();
+            let (name, kind, link, data): (&str, EntryType, Option<&str>, &[u8]) = match item {
+                Item::File(name, data) => (name, EntryType::Regular, None, data),
+                Item::Dir(name) => (name, EntryType::Directory, None, &[]),
+                Item::Symlink(name, link) => (name, EntryType::Symlink, Some(link), &[]),
+                Item::HardLink(name, link) => (name, EntryType::Link, Some(link), &[]),
+            };
+
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            if let Some(link) = link {
+                header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+            }
+
+            header.set_entry_type(kind);
+            header.set_mode(0o644);
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn same(path: &Path) -> Result<(usize, PathBuf), String> { Ok((0, path.to_path_buf())) }
+
+    fn check(items: &[Item], limits: Limits) -> Result<Vec<Option<u64>>, ArchiveError> { verify(tar(items).as_slice(), &[PathBuf::from("out")], &limits, &same) }
+
+    fn rejected(items: &[Item]) -> Vec<(String, String)> {
+        match check(items, Limits::default()) {
+            Err(ArchiveError::Rejected(entries)) => entries,
+            other => panic!("expected the archive to be rejected, got {other:?}"),
+        }
+    }
+
+    // a fresh directory under the system temp dir, removed when dropped
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("maid-archive-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(fs::canonicalize(dir).unwrap())
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) { let _ = fs::remove_dir_all(&self.0); }
+    }
+
+    #[test]
+    fn accepts_plain_files_and_relative_links() {
+        let sizes = check(&[Item::Dir("app"), Item::File("app/bin", b"binary"), Item::Symlink("app/current", "bin"), Item::Symlink("app/up", "../app")], Limits::default()).unwrap();
+        assert_eq!(sizes, [Some(6)]);
+    }
+
+    #[test]
+    fn rejects_parent_components() {
+        assert_eq!(rejected(&[Item::File("../escape", b"x")]), [(String::from("../escape"), String::from("climbs out with '..'"))]);
+        assert_eq!(rejected(&[Item::File("app/../../escape", b"x")]).len(), 1);
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        assert_eq!(rejected(&[Item::File("/etc/passwd", b"x")]), [(String::from("/etc/passwd"), String::from("absolute path"))]);
+    }
+
+    #[test]
+    fn rejects_hard_links() {
+        let entries = rejected(&[Item::File("a", b"x"), Item::HardLink("b", "a")]);
+        assert_eq!(entries, [(String::from("out/b"), String::from("hard links are not supported"))]);
+    }
+
+    #[test]
+    fn rejects_symlinks_leaving_the_root() {
+        let entries = rejected(&[Item::Symlink("app/link", "../../etc"), Item::Symlink("abs", "/etc")]);
+
+        assert_eq!(entries[0], (String::from("out/app/link"), String::from("symlink to '../../etc' escapes its destination")));
+        assert_eq!(entries[1], (String::from("out/abs"), String::from("symlink to absolute path '/etc'")));
+    }
+
+    #[test]
+    fn rejects_entries_below_a_symlink_of_the_archive() {
+        let entries = rejected(&[Item::Symlink("link", "inner"), Item::File("link/file", b"x")]);
+        assert_eq!(entries, [(String::from("out/link/file"), String::from("below the symlink 'link'"))]);
+    }
+
+    #[test]
+    fn lists_every_offending_entry() {
+        let entries = rejected(&[Item::File("../a", b"x"), Item::File("fine", b"x"), Item::File("/b", b"x"), Item::HardLink("c", "fine")]);
+        let message = ArchiveError::Rejected(entries).to_string();
+
+        assert!(message.starts_with("archive holds 3 unsafe entries, nothing was unpacked:"));
+        assert_eq!(message.lines().count(), 4);
+    }
+
+    #[test]
+    fn caps_the_number_of_entries() {
+        let limits = Limits { entries: 2, bytes: 1 << 20 };
+
+        assert!(check(&[Item::File("a", b"x"), Item::File("b", b"x")], limits).is_ok());
+        assert!(matches!(check(&[Item::File("a", b"x"), Item::File("b", b"x"), Item::File("c", b"x")], limits), Err(ArchiveError::TooManyEntries(2))));
+    }
+
+    #[test]
+    fn caps_the_unpacked_size() {
+        let limits = Limits { entries: 10, bytes: 10 };
+
+        assert!(check(&[Item::File("a", b"12345"), Item::File("b", b"12345")], limits).is_ok());
+        assert!(matches!(check(&[Item::File("a", b"12345"), Item::File("b", b"123456")], limits), Err(ArchiveError::TooLarge(10))));
+    }
+
+    #[test]
+    fn extracts_below_the_root() {
+        let scratch = Scratch::new();
+        let roots = [scratch.0.join("out")];
+        let archive = tar(&[Item::Dir("app"), Item::File("app/bin", b"binary"), Item::Symlink("app/current", "bin")]);
+
+        extract(|| Ok(archive.as_slice()), &roots, &Limits::default(), &same).unwrap();
+
+        assert_eq!(fs::read(roots[0].join("app/bin")).unwrap(), b"binary");
+        assert_eq!(fs::read_link(roots[0].join("app/current")).unwrap(), Path::new("bin"));
+    }
+
+    #[test]
+    fn unpacks_nothing_when_one_entry_is_unsafe() {
+        let scratch = Scratch::new();
+        let roots = [scratch.0.join("out")];
+        let archive = tar(&[Item::File("fine", b"x"), Item::File("../escape", b"x")]);
+
+        assert!(matches!(extract(|| Ok(archive.as_slice()), &roots, &Limits::default(), &same), Err(ArchiveError::Rejected(_))));
+        assert!(!roots[0].join("fine").exists());
+        assert!(!scratch.0.join("escape").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn does_not_write_through_a_symlink_on_disk() {
+        let scratch = Scratch::new();
+        let (root, outside) = (scratch.0.join("out"), scratch.0.join("outside"));
+
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+        let archive = tar(&[Item::File("link/file", b"x")]);
+        let result = extract(|| Ok(archive.as_slice()), &[root], &Limits::default(), &same);
+
+        assert!(matches!(result, Err(ArchiveError::Rejected(entries)) if entries[0].1 == "lands outside its destination"));
+        assert!(!outside.join("file").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn replaces_a_symlink_on_disk_instead_of_following_it() {
+        let scratch = Scratch::new();
+        let (root, outside) = (scratch.0.join("out"), scratch.0.join("outside"));
+
+        fs::create_dir_all(&root).unwrap();
+        fs::write(&outside, b"untouched").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("file")).unwrap();
+
+        let roots = [root];
+        let archive = tar(&[Item::File("file", b"new")]);
+        extract(|| Ok(archive.as_slice()), &roots, &Limits::default(), &same).unwrap();
+
+        assert_eq!(fs::read(&outside).unwrap(), b"untouched");
+        assert_eq!(fs::read(roots[0].join("file")).unwrap(), b"new");
+    }
+}
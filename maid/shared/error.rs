@@ -0,0 +1,75 @@
+use crate::{archive::ArchiveError, graph::GraphError};
+use std::{fmt, io};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound(String),
+    Parse(String),
+    MissingTask(String),
+    Argument(String),
+    RemoteOnly(String),
+    Graph(GraphError),
+    Archive(ArchiveError),
+    Cache(io::Error),
+    Remote(String),
+    Command { name: String, source: io::Error },
+    Io { context: String, source: io::Error },
+    Status(String),
+    Failed { task: String, index: usize, command: String, code: i32 },
+    RemoteFailed { task: String, code: Option<i32> },
+}
+
+impl Error {
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self { Error::Io { context: context.into(), source } }
+
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::Failed { code, .. } => *code,
+            Error::RemoteFailed { code: Some(code), .. } => *code,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(message) => f.write_str(message),
+            Error::Parse(message) => f.write_str(message),
+            Error::Argument(message) => f.write_str(message),
+            Error::MissingTask(task) => write!(f, "Could not find the task '{task}'. Does it exist?"),
+            Error::RemoteOnly(task) => write!(f, "Task '{task}' is remote only."),
+            Error::Graph(err) => write!(f, "{err}"),
+            Error::Archive(err) => write!(f, "Unable to unpack archive: {err}"),
+            Error::Cache(err) => write!(f, "Build cache error: {err}"),
+            Error::Remote(message) => write!(f, "{message}"),
+            Error::Command { name, source } => write!(f, "Cannot start command {name}: {source}"),
+            Error::Io { context, source } => write!(f, "{context}: {source}"),
+            Error::Status(message) => f.write_str(message),
+            Error::Failed { task, index, command, code } => write!(f, "Task '{task}' failed at command {index} ({command}) with status code {code}"),
+            Error::RemoteFailed { task, code: Some(code) } => write!(f, "Remote task '{task}' exited with status code {code}"),
+            Error::RemoteFailed { task, code: None } => write!(f, "Remote task '{task}' did not report an exit code"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Graph(err) => Some(err),
+            Error::Archive(err) => Some(err),
+            Error::Cache(err) | Error::Command { source: err, .. } | Error::Io { source: err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<GraphError> for Error {
+    fn from(err: GraphError) -> Self { Error::Graph(err) }
+}
+
+impl From<ArchiveError> for Error {
+    fn from(err: ArchiveError) -> Self { Error::Archive(err) }
+}
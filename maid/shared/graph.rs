@@ -0,0 +1,172 @@
+use crate::models::shared::Maidfile;
+use std::{collections::HashMap, fmt};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GraphError {
+    Missing { task: String, parent: String },
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Missing { task, parent } => write!(f, "Could not find the dependency '{task}' of task '{parent}'. Does it exist?"),
+            GraphError::Cycle(path) => write!(f, "Dependency cycle detected: {}", path.join(" -> ")),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: String,
+    pub verbose: bool,
+    pub depends: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Graph {
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+pub fn dependency_name(item: &str) -> (&str, bool) {
+    match item.strip_prefix("log:") {
+        Some(name) => (name, true),
+        None => (item, false),
+    }
+}
+
+impl Graph {
+    /// Builds the dependency graph of `root`, each task appears once and
+    /// nodes are stored in topological order with `root` as the last node.
+    pub fn new<T>(maidfile: &Maidfile<T>, root: &str) -> Result<Self, GraphError> {
+        let mut graph = Graph { nodes: vec![] };
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut marks: HashMap<String, Mark> = HashMap::new();
+        let mut stack: Vec<String> = vec![];
+
+        graph.visit(maidfile, root, &mut index, &mut marks, &mut stack)?;
+        Ok(graph)
+    }
+
+    fn visit<T>(&mut self, maidfile: &Maidfile<T>, name: &str, index: &mut HashMap<String, usize>, marks: &mut HashMap<String, Mark>, stack: &mut Vec<String>) -> Result<usize, GraphError> {
+        match marks.get(name) {
+            Some(Mark::Done) => return Ok(index[name]),
+            Some(Mark::Visiting) => {
+                let start = stack.iter().position(|item| item == name).unwrap_or_default();
+                let mut path = stack[start..].to_vec();
+                path.push(name.to_string());
+                return Err(GraphError::Cycle(path));
+            }
+            None => {}
+        }
+
+        marks.insert(name.to_string(), Mark::Visiting);
+        stack.push(name.to_string());
+
+        let mut depends = vec![];
+        let items = maidfile.tasks.get(name).and_then(|task| task.depends.clone()).unwrap_or_default();
+
+        for item in items.iter() {
+            let (dep, verbose) = dependency_name(item);
+
+            if !maidfile.tasks.contains_key(dep) {
+                return Err(GraphError::Missing {
+                    task: dep.to_string(),
+                    parent: name.to_string(),
+                });
+            }
+
+            let id = self.visit(maidfile, dep, index, marks, stack)?;
+            self.nodes[id].verbose |= verbose;
+
+            if !depends.contains(&id) {
+                depends.push(id);
+            }
+        }
+
+        stack.pop();
+        marks.insert(name.to_string(), Mark::Done);
+
+        self.nodes.push(Node {
+            name: name.to_string(),
+            verbose: false,
+            depends,
+        });
+
+        index.insert(name.to_string(), self.nodes.len() - 1);
+        Ok(self.nodes.len() - 1)
+    }
+
+    pub fn nodes(&self) -> &[Node] { &self.nodes }
+
+    pub fn root(&self) -> &Node { self.nodes.last().unwrap() }
+
+    /// Every transitive dependency of the root, in topological order.
+    pub fn dependencies(&self) -> &[Node] { &self.nodes[..self.nodes.len() - 1] }
+
+    pub fn dependents(&self, id: usize) -> Vec<usize> { self.nodes.iter().enumerate().filter(|(_, node)| node.depends.contains(&id)).map(|(index, _)| index).collect() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Map, Value};
+
+    fn maidfile(tasks: &[(&str, &[&str])]) -> Maidfile<Value> {
+        let tasks: Map<String, Value> = tasks.iter().map(|(name, depends)| (name.to_string(), json!({ "script": "true", "depends": depends }))).collect();
+        serde_json::from_value(json!({ "tasks": tasks })).unwrap()
+    }
+
+    fn names(nodes: &[Node]) -> Vec<&str> { nodes.iter().map(|node| node.name.as_str()).collect() }
+
+    #[test]
+    fn reports_the_whole_cycle() {
+        let maidfile = maidfile(&[("build", &["a"]), ("a", &["b"]), ("b", &["c"]), ("c", &["a"])]);
+        let err = Graph::new(&maidfile, "build").unwrap_err();
+
+        assert_eq!(err, GraphError::Cycle(vec!["a".into(), "b".into(), "c".into(), "a".into()]));
+        assert_eq!(err.to_string(), "Dependency cycle detected: a -> b -> c -> a");
+    }
+
+    #[test]
+    fn reports_a_task_depending_on_itself() {
+        let maidfile = maidfile(&[("build", &["build"])]);
+        assert_eq!(Graph::new(&maidfile, "build").unwrap_err(), GraphError::Cycle(vec!["build".into(), "build".into()]));
+    }
+
+    #[test]
+    fn reports_missing_dependencies() {
+        let maidfile = maidfile(&[("build", &["lint"]), ("lint", &["nope"])]);
+        let err = Graph::new(&maidfile, "build").unwrap_err();
+
+        assert_eq!(err, GraphError::Missing { task: "nope".into(), parent: "lint".into() });
+    }
+
+    #[test]
+    fn keeps_shared_dependencies_once() {
+        let maidfile = maidfile(&[("build", &["left", "right"]), ("left", &["base"]), ("right", &["base", "log:base"]), ("base", &[])]);
+        let graph = Graph::new(&maidfile, "build").unwrap();
+
+        assert_eq!(names(graph.nodes()), ["base", "left", "right", "build"]);
+        assert_eq!(names(graph.dependencies()), ["base", "left", "right"]);
+        assert_eq!(graph.root().depends, [1, 2]);
+        assert_eq!(graph.nodes()[2].depends, [0]);
+        assert_eq!(graph.dependents(0), [1, 2]);
+    }
+
+    #[test]
+    fn log_prefix_marks_the_dependency_verbose() {
+        let maidfile = maidfile(&[("build", &["log:lint", "test"]), ("lint", &[]), ("test", &[])]);
+        let graph = Graph::new(&maidfile, "build").unwrap();
+
+        assert_eq!(graph.nodes().iter().map(|node| (node.name.as_str(), node.verbose)).collect::<Vec<_>>(), [("lint", true), ("test", false), ("build", false)]);
+    }
+}
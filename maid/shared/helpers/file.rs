@@ -1,9 +1,9 @@
-use crate::log::prelude::*;
+use crate::{Error, Result};
 use std::env;
 
-pub fn get_current_working_dir() -> String {
+pub fn get_current_working_dir() -> Result<String> {
     match env::current_dir() {
-        Ok(path) => path.into_os_string().into_string().unwrap(),
-        Err(err) => error!(%err, "Unable to find current working dir"),
+        Ok(path) => Ok(path.into_os_string().into_string().unwrap()),
+        Err(err) => Err(Error::io("Unable to find current working dir", err)),
     }
 }
@@ -1,11 +1,11 @@
-use crate::log::prelude::*;
 use crate::models::{client::DisplayTask, shared::Maidfile};
+use crate::{Error, Result};
 
 impl<T: serde::Serialize> Maidfile<T> {
-    pub fn to_json(&self) -> String {
+    pub fn to_json(&self) -> Result<String> {
         match serde_json::to_string(&self) {
-            Ok(contents) => contents,
-            Err(err) => error!(%err, "Cannot read Maidfile"),
+            Ok(contents) => Ok(contents),
+            Err(err) => Err(Error::Parse(format!("Cannot read Maidfile: {err}"))),
         }
     }
 }
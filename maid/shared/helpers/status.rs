@@ -1,23 +1,32 @@
-use crate::log::prelude::*;
-use std::{io::Error, process::ExitStatus};
+use crate::{Error, Result};
+use std::{io, process::ExitStatus};
 
-pub fn error(debug_err: &str) {
-    error!("Unable to parse maidfile. Contains unexpected {debug_err} values.");
+pub fn error(debug_err: &str) -> Error { Error::Parse(format!("Unable to parse maidfile. Contains unexpected {debug_err} values.")) }
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
 }
 
-pub fn code(status: &Result<ExitStatus, Error>) -> i32 {
+#[cfg(not(unix))]
+fn signal(_: &ExitStatus) -> Option<i32> { None }
+
+pub fn code(status: &io::Result<ExitStatus>) -> Result<i32> {
     match status.as_ref() {
-        Ok(status) => match status.code() {
-            Some(iter) => iter,
-            None => error!("Missing status value"),
+        Ok(status) => match (status.code(), signal(status)) {
+            (Some(iter), _) => Ok(iter),
+            // killed by a signal, reported like a shell would
+            (None, Some(signal)) => Ok(128 + signal),
+            (None, None) => Err(Error::Status("Missing status value".to_string())),
         },
-        Err(err) => error!(%err, "Unknown error"),
+        Err(err) => Err(Error::Status(format!("Unknown error: {err}"))),
     }
 }
 
-pub fn success(status: &Result<ExitStatus, Error>) -> bool {
+pub fn success(status: &io::Result<ExitStatus>) -> Result<bool> {
     match status.as_ref() {
-        Ok(status) => status.success(),
-        Err(err) => error!(%err, "Unknown error"),
+        Ok(status) => Ok(status.success()),
+        Err(err) => Err(Error::Status(format!("Unknown error: {err}"))),
     }
 }
@@ -1,5 +1,12 @@
+pub mod archive;
 pub mod colors;
+pub mod error;
+pub mod graph;
 pub mod helpers;
 pub mod log;
 pub mod models;
+pub mod protocol;
 pub mod table;
+pub mod transfer;
+
+pub use error::{Error, Result};
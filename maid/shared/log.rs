@@ -22,10 +22,9 @@ macro_rules! warn {
 
 #[macro_export]
 macro_rules! error {
-    ($($field:tt)*) => {{
-        tracing::error!($($field)*);
-        std::process::exit(1);
-    }};
+    ($($field:tt)*) => {
+        tracing::error!($($field)*)
+    };
 }
 
 #[macro_export]
@@ -10,6 +10,10 @@ pub mod prelude {
 
 pub struct MaidFormatLayer;
 
+impl Default for MaidFormatLayer {
+    fn default() -> Self { Self::new() }
+}
+
 impl MaidFormatLayer {
     pub fn new() -> Self { Self }
 
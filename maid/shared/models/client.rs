@@ -1,11 +1,46 @@
 use crate::models::shared::{Maidfile, Remote};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CacheConfig {
     pub target: Vec<String>,
     pub hash: String,
+    #[serde(default)]
+    pub key: CacheKey,
+    #[serde(default)]
+    pub entries: Vec<CacheEntry>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CacheKey {
+    pub version: String,
+    pub inputs: String,
+    pub script: String,
+    pub table: BTreeMap<String, String>,
+    /// Hash of every input file, so a rebuild can say which ones changed.
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CacheEntry {
+    pub path: String,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -20,6 +55,8 @@ pub struct Task<T> {
     pub args: Vec<String>,
     pub silent: bool,
     pub dep: Dependency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<CacheKey>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -38,6 +75,13 @@ pub struct Runner<T> {
     pub project: PathBuf,
     pub silent: bool,
     pub dep: Dependency,
+    pub key: Option<CacheKey>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Ran,
+    Cached,
 }
 
 #[derive(Debug)]
@@ -46,49 +90,6 @@ pub struct DisplayTask {
     pub formatted: String,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
-pub enum Level {
-    None,
-    Fatal,
-    Docker,
-    Debug,
-    Error,
-    Notice,
-    Info,
-    Build,
-    Warning,
-    Success,
-}
-
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub enum Kind {
-    Done,
-    Binary,
-    Message,
-}
-
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct Websocket {
-    pub level: Level,
-    pub kind: Kind,
-    pub time: i64,
-    pub message: Option<String>,
-}
-
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct ConnectionInfo {
-    pub name: String,
-    pub remote: Remote,
-    pub args: Vec<String>,
-    pub script: Vec<String>,
-}
-
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct ConnectionData<T> {
-    pub info: ConnectionInfo,
-    pub maidfile: Maidfile<T>,
-}
-
 #[derive(Deserialize)]
 pub struct UpdateData {
     pub version: String,
@@ -1,3 +1,2 @@
 pub mod client;
-pub mod server;
 pub mod shared;
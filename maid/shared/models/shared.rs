@@ -1,6 +1,66 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::BTreeMap;
 
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+// pull takes a path, a table with a destination, or a list of either
+fn pulls<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Pull>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Item {
+        Path(String),
+        Mapped(Pull),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Item),
+        Many(Vec<Item>),
+    }
+
+    let items = match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(item) => vec![item],
+        OneOrMany::Many(items) => items,
+    };
+
+    Ok(items
+        .into_iter()
+        .map(|item| match item {
+            Item::Path(path) => Pull { path, to: None },
+            Item::Mapped(pull) => pull,
+        })
+        .collect())
+}
+
+fn scalar<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Scalar {
+        String(String),
+        Integer(i64),
+        Boolean(bool),
+    }
+
+    Ok(Option::<Scalar>::deserialize(deserializer)?.map(|value| match value {
+        Scalar::String(value) => value,
+        Scalar::Integer(value) => value.to_string(),
+        Scalar::Boolean(value) => value.to_string(),
+    }))
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Maidfile<T> {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -20,6 +80,16 @@ pub struct Project {
     pub version: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub server: Option<Server>, // wip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<Shell>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Shell {
+    Enabled(bool),
+    Program(String),
+    Command(Vec<String>),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -50,20 +120,78 @@ pub struct Tasks<T> {
     pub remote: Option<Remote>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub depends: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continue_on_error: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<Shell>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<BTreeMap<String, Argument>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watch: Option<Vec<String>>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Argument {
+    #[serde(default, rename = "type")]
+    pub kind: ArgumentKind,
+    #[serde(default, deserialize_with = "scalar", skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArgumentKind {
+    #[default]
+    String,
+    Int,
+    Bool,
+    Enum,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Cache {
-    pub path: String,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub path: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
     pub target: Vec<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Remote {
     pub push: Vec<String>,
-    pub pull: String,
+    #[serde(default, deserialize_with = "pulls", skip_serializing_if = "Vec::is_empty")]
+    pub pull: Vec<Pull>,
+    /// Pull even when the script failed, for test reports and logs.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pull_on_failure: bool,
     pub image: String,
     pub shell: String,
     pub silent: bool,
     pub exclusive: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+    #[serde(default, deserialize_with = "scalar", skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// A path or glob in the workdir of a remote task, every match lands in `to` under its own name.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Pull {
+    pub path: String,
+    /// Defaults to the current directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
 }
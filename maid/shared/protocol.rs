@@ -0,0 +1,290 @@
+use crate::models::{
+    client::CacheEntry,
+    shared::{Maidfile, Remote},
+};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bumped whenever a frame changes shape, both sides refuse to talk across versions.
+pub const VERSION: u32 = 1;
+
+/// Features this build speaks, exchanged during the handshake. A peer missing any of them is refused.
+pub const CAPABILITIES: &[&str] = &["exit-code", "chunked-transfer", "incremental-push", "detached-jobs", "run-settings", "script-steps", "task-graph", "multi-pull"];
+
+/// Artifacts are streamed in binary frames of at most this many bytes.
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum Level {
+    None,
+    Fatal,
+    Docker,
+    Debug,
+    Error,
+    Notice,
+    Info,
+    Build,
+    Warning,
+    Success,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Kind {
+    Done,
+    Binary,
+    Message,
+    /// The server accepted the run as a job, the message holds its id.
+    Job,
+    /// A script line started or ended, details are in `step`.
+    Step,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Phase {
+    Start,
+    End,
+}
+
+/// One line of a remote script, every line runs as a step of its own.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Step {
+    /// The task the line belongs to, the remote task itself or one of its dependencies.
+    pub task: String,
+    /// Position of the line in the task's script, counting from 1.
+    pub index: usize,
+    pub total: usize,
+    /// The line as written in the Maidfile.
+    pub command: String,
+    pub phase: Phase,
+    /// Milliseconds the line ran for, once it ended.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+}
+
+/// First frame in both directions of a websocket session.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Handshake {
+    pub protocol: u32,
+    pub version: String,
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Response {
+    pub level: Level,
+    pub kind: Kind,
+    pub time: i64,
+    pub message: Option<String>,
+    #[serde(default)]
+    pub code: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<Step>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ConnectionInfo {
+    pub name: String,
+    pub remote: Remote,
+    pub args: Vec<String>,
+    /// Leave once the job is accepted instead of following its output.
+    #[serde(default)]
+    pub detach: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ConnectionData<T> {
+    pub info: ConnectionInfo,
+    pub maidfile: Maidfile<T>,
+}
+
+/// What the client asks for once the handshake is done.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Request<T> {
+    /// Pick up a pulled artifact the server staged before the connection dropped.
+    Fetch { fetch: String },
+    /// Replay the output of a job, and keep streaming it until the job ends when following.
+    Attach {
+        attach: String,
+        #[serde(default)]
+        follow: bool,
+    },
+    Run(Box<ConnectionData<T>>),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// What the server keeps about a job next to its log and artifact.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JobSummary {
+    pub id: String,
+    pub task: String,
+    pub project: String,
+    pub token: String,
+    pub image: String,
+    pub status: JobStatus,
+    pub code: Option<i64>,
+    pub created: i64,
+    pub finished: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<String>,
+}
+
+/// Everything the client wants in the workspace, files are named by the blake3 hash of their contents.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    pub entries: Vec<CacheEntry>,
+}
+
+/// Hashes from a manifest the server does not hold yet, only these are uploaded.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Missing {
+    pub missing: Vec<String>,
+}
+
+/// Control frames around a chunked transfer, the chunks themselves travel as binary frames.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "transfer", rename_all = "lowercase")]
+pub enum Transfer {
+    /// Sender announces an artifact, the id is the blake3 hash of its contents.
+    Offer { id: String, size: u64 },
+    /// Receiver wants every chunk starting at `from`, sent again after a round with bad chunks.
+    Resume { id: String, from: u64 },
+    /// Receiver holds the whole artifact and it matches the id.
+    Complete { id: String },
+}
+
+/// Milliseconds since the unix epoch, the timestamp used in every frame.
+pub fn now() -> i64 { SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis() as i64) }
+
+impl Default for Handshake {
+    fn default() -> Self { Self::new() }
+}
+
+impl Handshake {
+    pub fn new() -> Self {
+        Self {
+            protocol: VERSION,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            capabilities: CAPABILITIES.iter().map(|item| item.to_string()).collect(),
+        }
+    }
+
+    pub fn supports(&self, capability: &str) -> bool { self.capabilities.iter().any(|item| item == capability) }
+
+    /// Describes why the peer cannot be talked to, none when the versions match and it speaks every capability.
+    pub fn mismatch(&self, peer: &str) -> Option<String> {
+        let missing: Vec<&str> = CAPABILITIES.iter().copied().filter(|capability| !self.supports(capability)).collect();
+
+        if self.protocol != VERSION {
+            return Some(format!(
+                "protocol mismatch, maid {} speaks v{VERSION} but the {peer} (maid {}) speaks v{}, upgrade the older one",
+                env!("CARGO_PKG_VERSION"),
+                self.version,
+                self.protocol
+            ));
+        }
+
+        match missing.is_empty() {
+            true => None,
+            false => Some(format!("the {peer} (maid {}) lacks {}, upgrade it", self.version, missing.join(", "))),
+        }
+    }
+}
+
+impl JobStatus {
+    pub fn finished(&self) -> bool { !matches!(self, JobStatus::Queued | JobStatus::Running) }
+}
+
+impl std::fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobStatus::Queued => f.write_str("queued"),
+            JobStatus::Running => f.write_str("running"),
+            JobStatus::Succeeded => f.write_str("succeeded"),
+            JobStatus::Failed => f.write_str("failed"),
+            JobStatus::Cancelled => f.write_str("cancelled"),
+        }
+    }
+}
+
+impl Transfer {
+    pub fn to_json(&self) -> String { serde_json::to_string(self).unwrap_or_default() }
+}
+
+impl Response {
+    pub fn new(level: Level, kind: Kind, message: Option<String>) -> Self {
+        Self {
+            level,
+            kind,
+            message,
+            code: None,
+            step: None,
+            time: now(),
+        }
+    }
+
+    pub fn message(level: Level, message: impl Into<String>) -> Self { Self::new(level, Kind::Message, Some(message.into())) }
+
+    pub fn done(code: Option<i64>) -> Self {
+        let level = match code {
+            Some(0) => Level::Success,
+            _ => Level::Fatal,
+        };
+
+        Self { code, ..Self::new(level, Kind::Done, None) }
+    }
+
+    /// Reports a step, the exit code only goes along once it ended.
+    pub fn step(step: Step, code: Option<i64>) -> Self {
+        let level = match (step.phase, code) {
+            (Phase::Start, _) => Level::Build,
+            (Phase::End, Some(0)) => Level::Success,
+            (Phase::End, _) => Level::Error,
+        };
+
+        Self {
+            code,
+            step: Some(step),
+            ..Self::new(level, Kind::Step, None)
+        }
+    }
+
+    pub fn to_json(&self) -> String { serde_json::to_string(self).unwrap_or_default() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_accepts_a_matching_peer() { assert!(Handshake::new().mismatch("server").is_none()) }
+
+    #[test]
+    fn handshake_refuses_another_version() {
+        let peer = Handshake { protocol: VERSION + 1, ..Handshake::new() };
+        assert!(peer.mismatch("server").unwrap().contains("protocol mismatch"));
+    }
+
+    #[test]
+    fn handshake_refuses_missing_capabilities() {
+        let mut peer = Handshake::new();
+        peer.capabilities.retain(|item| item != "multi-pull");
+
+        assert!(!peer.supports("multi-pull"));
+        assert!(peer.mismatch("client").unwrap().contains("lacks multi-pull"));
+
+        // capabilities this build does not know about are fine
+        peer.capabilities.push("multi-pull".to_string());
+        peer.capabilities.push("from-the-future".to_string());
+        assert!(peer.mismatch("client").is_none());
+    }
+}
@@ -1,62 +1,86 @@
 use crate::helpers;
 use crate::log::prelude::*;
 use crate::models::shared::Maidfile;
+use crate::{Error, Result};
 
 use macros_rs::{exp::ternary, fmt::str};
 use std::path::PathBuf;
 use std::{collections::BTreeMap, collections::HashMap, env};
 use text_placeholder::Template;
 
-pub fn create<T: ToString>(values: Maidfile<T>, args: &Vec<String>, project: PathBuf) -> HashMap<&str, &str> {
+/// What the `dir.*` placeholders point at.
+pub struct Dirs {
+    pub current: PathBuf,
+    pub home: PathBuf,
+    pub project: PathBuf,
+}
+
+/// Borrows an owned table the way templates want it.
+pub fn view(table: &HashMap<String, String>) -> HashMap<&str, &str> { table.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect() }
+
+/// Builds the placeholder table from the given directories, without reading or changing the process environment.
+pub fn build<T: ToString>(env: Option<&BTreeMap<String, T>>, args: &[String], dirs: &Dirs) -> HashMap<String, String> {
     let mut table = HashMap::new();
-    let empty_env: BTreeMap<String, T> = BTreeMap::new();
 
     trace!(value = env::consts::OS, "os.platform");
     trace!(value = env::consts::ARCH, "os.arch");
 
-    table.insert("os.platform", env::consts::OS);
-    table.insert("os.arch", env::consts::ARCH);
+    table.insert(string("os.platform"), string(env::consts::OS));
+    table.insert(string("os.arch"), string(env::consts::ARCH));
 
-    match env::current_dir() {
-        Ok(path) => {
-            trace!(value = path.display().to_string(), "dir.current");
-            table.insert("dir.current", helpers::string::path_to_str(&path));
-        }
-        Err(err) => error!(%err, "Current directory could not be added as script variable."),
+    for (name, path) in [("dir.current", &dirs.current), ("dir.home", &dirs.home), ("dir.project", &dirs.project)] {
+        trace!(value = path.display().to_string(), "{name}");
+        table.insert(string(name), path.to_string_lossy().into_owned());
     }
 
-    match home::home_dir() {
-        Some(path) => {
-            trace!(value = path.display().to_string(), "dir.home");
-            table.insert("dir.home", helpers::string::path_to_str(&path));
+    let mut pos = 0;
+
+    for arg in args {
+        match arg.strip_prefix("--").and_then(|arg| arg.split_once('=')) {
+            Some((name, value)) => {
+                trace!(value, "arg.{name}");
+                table.insert(format!("arg.{name}"), string(value));
+            }
+            None => {
+                trace!(value = arg, "arg.{pos}");
+                table.insert(format!("arg.{pos}"), arg.clone());
+                pos += 1;
+            }
         }
-        None => error!("Home directory could not be added as script variable."),
     }
 
-    trace!(value = project.display().to_string(), "dir.project");
-    table.insert("dir.project", helpers::string::path_to_str(&project));
+    // later values may use earlier ones
+    for (key, value) in env.into_iter().flatten() {
+        let value = value.to_string();
+        let filled = Template::new_with_placeholder(&value, "%{", "}").fill_with_hashmap(&view(&table));
+        let value_formatted = ternary!(value.starts_with("\""), helpers::string::trim_start_end(&filled), filled.as_str()).replace("\"", "\\\"");
 
-    for (pos, arg) in args.iter().enumerate() {
-        trace!(value = arg, "arg.{pos}");
-        table.insert(str!(format!("arg.{pos}")), arg);
+        trace!(value = value_formatted, "env.{key}");
+        table.insert(format!("env.{key}"), value_formatted);
     }
 
-    let user_env = match &values.env {
-        Some(env) => env.iter(),
-        None => empty_env.iter(),
+    table
+}
+
+fn string(value: &str) -> String { value.to_string() }
+
+pub fn create<T: ToString>(values: Maidfile<T>, args: &[String], project: PathBuf) -> Result<HashMap<&str, &str>> {
+    let current = match env::current_dir() {
+        Ok(path) => path,
+        Err(err) => return Err(Error::io("Current directory could not be added as script variable", err)),
     };
 
-    for (key, value) in user_env {
-        let value_formatted = ternary!(
-            value.to_string().starts_with("\""),
-            helpers::string::trim_start_end(str!(Template::new_with_placeholder(&value.to_string(), "%{", "}").fill_with_hashmap(&table))).replace("\"", "\\\""),
-            str!(Template::new_with_placeholder(&value.to_string(), "%{", "}").fill_with_hashmap(&table)).replace("\"", "\\\"")
-        );
+    let home = match home::home_dir() {
+        Some(path) => path,
+        None => return Err(Error::NotFound("Home directory could not be added as script variable.".to_string())),
+    };
 
-        trace!(value = value_formatted, "env.{key}");
-        env::set_var(key, value_formatted.clone());
-        table.insert(str!(format!("env.{}", key.clone())), str!(value_formatted));
+    let table = build(values.env.as_ref(), args, &Dirs { current, home, project });
+
+    // local scripts inherit the Maidfile's env from maid itself
+    for key in values.env.iter().flat_map(|env| env.keys()) {
+        env::set_var(key, &table[&format!("env.{key}")]);
     }
 
-    return table;
+    Ok(table.into_iter().map(|(key, value)| (str!(key), str!(value))).collect())
 }
@@ -0,0 +1,284 @@
+use crate::protocol::CHUNK_SIZE;
+
+use macros_rs::exp::ternary;
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+// index (u64, big endian) followed by the blake3 hash of index and data
+const HEADER: usize = 8 + 32;
+
+pub struct Chunk {
+    pub index: u64,
+    pub data: Vec<u8>,
+    checksum: [u8; 32],
+}
+
+/// Receiving end of a transfer, verified chunks are appended to `{dir}/{id}.part`.
+pub struct Partial {
+    id: String,
+    size: u64,
+    path: PathBuf,
+    file: File,
+    next: u64,
+}
+
+fn digest(index: u64, data: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&index.to_be_bytes());
+    hasher.update(data);
+    *hasher.finalize().as_bytes()
+}
+
+/// Ids come from the peer and end up in file names, so only blake3 hex digests are accepted.
+pub fn valid_id(id: &str) -> bool { id.len() == 64 && id.bytes().all(|byte| byte.is_ascii_hexdigit()) }
+
+pub fn chunks(size: u64) -> u64 { size.div_ceil(CHUNK_SIZE as u64) }
+
+pub fn checksum(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+impl Chunk {
+    /// Reads chunk `index` of a file and frames it for sending.
+    pub fn read(file: &mut File, index: u64) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(CHUNK_SIZE);
+
+        file.seek(SeekFrom::Start(index * CHUNK_SIZE as u64))?;
+        file.take(CHUNK_SIZE as u64).read_to_end(&mut data)?;
+
+        let mut frame = Vec::with_capacity(HEADER + data.len());
+        frame.extend_from_slice(&index.to_be_bytes());
+        frame.extend_from_slice(&digest(index, &data));
+        frame.extend_from_slice(&data);
+
+        Ok(frame)
+    }
+
+    pub fn decode(frame: &[u8]) -> Option<Self> {
+        if frame.len() < HEADER || frame.len() > HEADER + CHUNK_SIZE {
+            return None;
+        }
+
+        Some(Self {
+            index: u64::from_be_bytes(frame[..8].try_into().ok()?),
+            checksum: frame[8..HEADER].try_into().ok()?,
+            data: frame[HEADER..].to_vec(),
+        })
+    }
+
+    pub fn verify(&self) -> bool { digest(self.index, &self.data) == self.checksum }
+}
+
+impl Partial {
+    /// Picks up where an earlier attempt stopped, a finished artifact is reused as is.
+    pub fn open(dir: &Path, id: &str, size: u64) -> io::Result<Self> {
+        if !valid_id(id) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid transfer id '{id}'")));
+        }
+
+        fs::create_dir_all(dir)?;
+
+        let path = dir.join(format!("{id}.part"));
+        let finished = dir.join(id);
+
+        if finished.is_file() && fs::metadata(&finished)?.len() == size {
+            fs::rename(&finished, &path)?;
+        }
+
+        let file = OpenOptions::new().create(true).truncate(false).read(true).write(true).open(&path)?;
+        let len = file.metadata()?.len().min(size);
+
+        // a finished artifact keeps its short last chunk, anything else is cut back to whole chunks
+        let next = match len == size {
+            true => chunks(size),
+            false => len / CHUNK_SIZE as u64,
+        };
+
+        file.set_len(ternary!(next == chunks(size), size, next * CHUNK_SIZE as u64))?;
+
+        Ok(Self { id: id.to_string(), size, path, file, next })
+    }
+
+    pub fn next(&self) -> u64 { self.next }
+
+    pub fn received(&self) -> u64 { (self.next * CHUNK_SIZE as u64).min(self.size) }
+
+    pub fn done(&self) -> bool { self.next == chunks(self.size) }
+
+    /// Appends the chunk when it is the next one and intact, anything else is dropped.
+    pub fn write(&mut self, chunk: &Chunk) -> io::Result<bool> {
+        if chunk.index != self.next || !chunk.verify() {
+            return Ok(false);
+        }
+
+        self.file.seek(SeekFrom::Start(self.next * CHUNK_SIZE as u64))?;
+        self.file.write_all(&chunk.data)?;
+        self.next += 1;
+
+        Ok(true)
+    }
+
+    /// Checks the whole artifact against its id, a mismatch throws the partial away.
+    pub fn finish(mut self) -> io::Result<Option<PathBuf>> {
+        self.file.flush()?;
+
+        if checksum(&self.path)? != self.id {
+            fs::remove_file(&self.path)?;
+            return Ok(None);
+        }
+
+        let finished = self.path.with_extension("");
+        fs::rename(&self.path, &finished)?;
+
+        Ok(Some(finished))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK: u64 = CHUNK_SIZE as u64;
+
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("maid-transfer-{}", uuid::Uuid::new_v4().simple()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) { let _ = fs::remove_dir_all(&self.0); }
+    }
+
+    // two whole chunks and a short last one
+    fn artifact(scratch: &Scratch) -> (File, Vec<u8>, String) {
+        let data: Vec<u8> = (0..CHUNK * 2 + CHUNK / 2).map(|index| (index % 251) as u8).collect();
+        let path = scratch.0.join("source");
+
+        fs::write(&path, &data).unwrap();
+        (File::open(&path).unwrap(), data, checksum(&path).unwrap())
+    }
+
+    fn chunk(file: &mut File, index: u64) -> Chunk { Chunk::decode(&Chunk::read(file, index).unwrap()).unwrap() }
+
+    #[test]
+    fn chunks_round_up() {
+        assert_eq!(chunks(0), 0);
+        assert_eq!(chunks(1), 1);
+        assert_eq!(chunks(CHUNK), 1);
+        assert_eq!(chunks(CHUNK + 1), 2);
+    }
+
+    #[test]
+    fn receives_chunks_in_order() {
+        let scratch = Scratch::new();
+        let (mut file, data, id) = artifact(&scratch);
+        let staged = scratch.0.join("staged");
+        let mut partial = Partial::open(&staged, &id, data.len() as u64).unwrap();
+
+        assert_eq!(partial.next(), 0);
+
+        for index in 0..3 {
+            assert!(partial.write(&chunk(&mut file, index)).unwrap());
+            assert_eq!(partial.received(), ((index + 1) * CHUNK).min(data.len() as u64));
+        }
+
+        assert!(partial.done());
+
+        let path = partial.finish().unwrap().unwrap();
+        assert_eq!(path, staged.join(&id));
+        assert_eq!(fs::read(path).unwrap(), data);
+    }
+
+    #[test]
+    fn drops_chunks_out_of_order_or_damaged() {
+        let scratch = Scratch::new();
+        let (mut file, data, id) = artifact(&scratch);
+        let mut partial = Partial::open(&scratch.0.join("staged"), &id, data.len() as u64).unwrap();
+
+        assert!(!partial.write(&chunk(&mut file, 1)).unwrap());
+
+        let mut damaged = chunk(&mut file, 0);
+        damaged.data[0] ^= 1;
+        assert!(!partial.write(&damaged).unwrap());
+
+        assert_eq!(partial.next(), 0);
+        assert!(partial.write(&chunk(&mut file, 0)).unwrap());
+        assert_eq!(partial.next(), 1);
+    }
+
+    #[test]
+    fn resumes_from_the_last_whole_chunk() {
+        let scratch = Scratch::new();
+        let (mut file, data, id) = artifact(&scratch);
+        let staged = scratch.0.join("staged");
+
+        // an earlier attempt got one and a half chunks down before the connection dropped
+        fs::create_dir_all(&staged).unwrap();
+        fs::write(staged.join(format!("{id}.part")), &data[..(CHUNK + CHUNK / 2) as usize]).unwrap();
+
+        let mut partial = Partial::open(&staged, &id, data.len() as u64).unwrap();
+
+        assert_eq!(partial.next(), 1);
+        assert_eq!(partial.received(), CHUNK);
+        assert_eq!(fs::metadata(staged.join(format!("{id}.part"))).unwrap().len(), CHUNK);
+
+        for index in 1..3 {
+            assert!(partial.write(&chunk(&mut file, index)).unwrap());
+        }
+
+        assert_eq!(fs::read(partial.finish().unwrap().unwrap()).unwrap(), data);
+    }
+
+    #[test]
+    fn reuses_a_finished_artifact() {
+        let scratch = Scratch::new();
+        let (_, data, id) = artifact(&scratch);
+        let staged = scratch.0.join("staged");
+
+        fs::create_dir_all(&staged).unwrap();
+        fs::write(staged.join(&id), &data).unwrap();
+
+        let partial = Partial::open(&staged, &id, data.len() as u64).unwrap();
+
+        assert!(partial.done());
+        assert_eq!(partial.received(), data.len() as u64);
+        assert_eq!(fs::read(partial.finish().unwrap().unwrap()).unwrap(), data);
+    }
+
+    #[test]
+    fn throws_away_an_artifact_that_misses_its_id() {
+        let scratch = Scratch::new();
+        let (mut file, data, _) = artifact(&scratch);
+        let staged = scratch.0.join("staged");
+        let id = "0".repeat(64);
+        let mut partial = Partial::open(&staged, &id, data.len() as u64).unwrap();
+
+        for index in 0..3 {
+            assert!(partial.write(&chunk(&mut file, index)).unwrap());
+        }
+
+        assert!(partial.finish().unwrap().is_none());
+        assert!(!staged.join(format!("{id}.part")).exists());
+        assert!(!staged.join(&id).exists());
+    }
+
+    #[test]
+    fn refuses_ids_that_are_not_digests() {
+        let scratch = Scratch::new();
+
+        for id in ["../escape", "", &"g".repeat(64), &"a".repeat(63)] {
+            assert!(Partial::open(&scratch.0, id, 1).is_err(), "{id} should be refused");
+        }
+    }
+}